@@ -1 +1 @@
-cmd /C start "{name}" /B "%LOCALAPPDATA%\warp\packages\{executable}\{executable}" "{url}" --name "{name}" inplace
\ No newline at end of file
+cmd /C start "{name}" /B "%LOCALAPPDATA%\warp\packages\{executable}\{executable}" "{url}" --name "{name}"{background} inplace
\ No newline at end of file
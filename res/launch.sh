@@ -1,3 +1,3 @@
 #!/usr/bin/env bash
 DIR=$(cd "$(dirname "$0")"; pwd)
-"$DIR/{executable}" "{url}" --name "{title}"  inplace
\ No newline at end of file
+"$DIR/{executable}" "{url}" --name "{title}"{background}  inplace
\ No newline at end of file
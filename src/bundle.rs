@@ -1,9 +1,18 @@
 use crate::infer;
+use crate::infer::color::{self, Colors};
+use crate::infer::transform::{scale, Upscale};
 use icns;
+use idna;
 use std::io::{BufWriter, Write};
 use std::{env, error::Error, fs, path::PathBuf, process::Command};
 use url::Url;
 
+/// Size, in pixels, that icons smaller than it are enlarged to for macos.
+const ICNS_SIZE: u32 = 512;
+
+/// Size, in pixels, that icons smaller than it are enlarged to for windows.
+const ICO_SIZE: u32 = 255;
+
 /// Bundler is any object that can produce an executable bundle.
 /// This allows us to be polymorphic across operating systems (macos, windows,
 /// linux) and their various ways of handling an app bundle.
@@ -11,6 +20,142 @@ pub trait Bundler {
     fn bundle(self) -> Result<(), Box<dyn Error>>;
 }
 
+/// Derive a name for the executable of an app that is safe to use on disk and
+/// in launch scripts: lowercase ASCII without whitespace.
+///
+/// Accented letters are replaced by their unaccented counterparts, so "München"
+/// becomes "munchen". Names with nothing left to use, such as those written in
+/// non-Latin scripts, fall back to their punycode form.
+pub fn executable(name: &str) -> String {
+    let mut executable = String::new();
+    for c in name.chars().filter(|c| !c.is_whitespace()) {
+        match c {
+            'a'..='z' | '0'..='9' | '-' | '_' | '.' => executable.push(c),
+            'A'..='Z' => executable.push(c.to_ascii_lowercase()),
+            _ => executable.push_str(transliterate(c)),
+        }
+    }
+    if executable.chars().any(|c| c.is_ascii_alphanumeric()) {
+        return executable;
+    }
+    let compact: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+    match idna::domain_to_ascii(&compact) {
+        Ok(ascii)
+            if ascii.chars().any(|c| c.is_ascii_alphanumeric())
+                && ascii
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') =>
+        {
+            ascii.replace('.', "-")
+        }
+        _ => "app".into(),
+    }
+}
+
+/// Derive a file name for an app from its display name, for bundles such as
+/// "AC-DC.app" that are named the way users see them. Path separators and
+/// other characters file systems reject are replaced by dashes.
+pub fn file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+            c if c.is_control() => '-',
+            c => c,
+        })
+        .collect();
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if name.is_empty() {
+        return "app".into();
+    }
+    name.into()
+}
+
+/// ASCII replacement for common accented Latin letters, or nothing.
+fn transliterate(c: char) -> &'static str {
+    match c.to_lowercase().next().unwrap_or(c) {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'ğ' => "g",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => "i",
+        'ł' | 'ľ' | 'ĺ' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'œ' => "oe",
+        'ř' | 'ŕ' => "r",
+        'ś' | 'š' | 'ş' | 'ș' => "s",
+        'ß' => "ss",
+        'ť' | 'ţ' | 'ț' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => "",
+    }
+}
+
+/// Arguments that give the app's runtime its loading screen color, to append
+/// to its command line in a launch script.
+fn background_args(colors: &Colors) -> String {
+    colors
+        .background
+        .map(|background| format!(" --background-color \"{}\"", color::hex(background)))
+        .unwrap_or_default()
+}
+
+/// Build an icns file from the file icon was decoded from, without decoding
+/// and re-encoding it: icns files are used as they are, and png files of a
+/// size icns supports are wrapped as its only image.
+///
+/// Icons smaller than ICNS_SIZE have no verbatim file, so that they're
+/// enlarged instead.
+fn icns_verbatim(icon: &infer::Icon) -> Option<Vec<u8>> {
+    let original = icon.original.as_ref()?;
+    if icon.img.width().max(icon.img.height()) < ICNS_SIZE {
+        return None;
+    }
+    match original.mime.as_str() {
+        "image/icns" => Some(original.data.to_vec()),
+        "image/png" => {
+            let kind: &[u8; 4] = match icon.img.dimensions() {
+                (16, 16) => b"icp4",
+                (32, 32) => b"icp5",
+                (64, 64) => b"icp6",
+                (128, 128) => b"ic07",
+                (256, 256) => b"ic08",
+                (512, 512) => b"ic09",
+                (1024, 1024) => b"ic10",
+                _ => return None,
+            };
+            // An icns file is a header followed by entries, each a type
+            // and a length that includes their own 8 byte header.
+            let entry = original.data.len() as u32 + 8;
+            let mut data = Vec::with_capacity(entry as usize + 8);
+            data.extend_from_slice(b"icns");
+            data.extend_from_slice(&(entry + 8).to_be_bytes());
+            data.extend_from_slice(kind);
+            data.extend_from_slice(&entry.to_be_bytes());
+            data.extend_from_slice(&original.data);
+            Some(data)
+        }
+        _ => None,
+    }
+}
+
+/// The ico file icon was decoded from, which can go into an executable as it
+/// is, keeping every size. Icons smaller than ICO_SIZE have none, so that
+/// they're enlarged instead.
+fn ico_verbatim(icon: &infer::Icon) -> Option<&[u8]> {
+    let original = icon.original.as_ref()?;
+    if original.mime != "image/x-icon" || icon.img.width().max(icon.img.height()) < ICO_SIZE {
+        return None;
+    }
+    Some(&original.data)
+}
+
 // Darwin bundles a macos app bundle.
 pub struct Darwin<'a> {
     /// Output directory. Defaults to current working directory.
@@ -21,17 +166,16 @@ pub struct Darwin<'a> {
     pub url: &'a Url,
     /// Filepath to icon.
     pub icon: infer::Icon,
+    /// Accent colors of the site.
+    pub colors: Colors,
+    /// How an icon smaller than the bundle's icons is enlarged.
+    pub upscale: Upscale,
 }
 
 impl Bundler for Darwin<'_> {
     fn bundle(self) -> Result<(), Box<dyn Error>> {
-        let executable = self
-            .name
-            .chars()
-            .filter(|c| !c.is_whitespace())
-            .map(|c| c.to_ascii_lowercase())
-            .collect::<String>();
-        let app = PathBuf::from(&self.dir).join(format!("{0}.app", &self.name));
+        let executable = executable(&self.name);
+        let app = PathBuf::from(&self.dir).join(format!("{0}.app", file_name(&self.name)));
         let plist = app.join("Contents/Info.plist");
         let wrapper = app.join(format!("Contents/MacOS/{0}.sh", &executable));
         let icon_path = app.join("Contents/Resources/icon.icns");
@@ -51,11 +195,23 @@ impl Bundler for Darwin<'_> {
                 executable = &executable,
                 title = &self.name,
                 url = &self.url.as_str(),
+                background = background_args(&self.colors),
             )
             .as_bytes(),
         )?;
         Command::new("chmod").arg("+x").arg(&wrapper).output()?;
-        icns::Encoder::new(BufWriter::new(fs::File::create(&icon_path)?)).encode(&self.icon.img)?;
+        match icns_verbatim(&self.icon) {
+            Some(data) => fs::write(&icon_path, data)?,
+            None => {
+                // Small icons are enlarged here, crisply when they're tiny,
+                // rather than smoothed by the encoder.
+                let img = match self.icon.img.width().max(self.icon.img.height()) {
+                    side if side < ICNS_SIZE => scale(&self.icon.img, ICNS_SIZE, self.upscale),
+                    _ => self.icon.img.clone(),
+                };
+                icns::Encoder::new(BufWriter::new(fs::File::create(&icon_path)?)).encode(&img)?
+            }
+        }
         Ok(())
     }
 }
@@ -66,6 +222,8 @@ pub struct Windows<'a> {
     pub name: &'a str,
     pub url: &'a Url,
     pub icon: infer::Icon,
+    pub colors: Colors,
+    pub upscale: Upscale,
 }
 
 /// Bundler uses an executable "warp-packer" to create a standalone binary,
@@ -76,13 +234,14 @@ pub struct Windows<'a> {
 impl Bundler for Windows<'_> {
     #[cfg(target_os = "windows")]
     fn bundle(self) -> Result<(), Box<dyn Error>> {
-        use image::imageops::{resize, Lanczos3};
+        let executable = executable(&self.name);
+        let exe = format!("{}.exe", &executable);
         let root = PathBuf::from(&self.dir);
         let workspace = root.join("tmp");
-        let bundle = workspace.join(format!("{}.exe", &self.name));
+        let bundle = workspace.join(&exe);
         let packer = workspace.join("warp-packer.exe");
-        let input = workspace.join(&self.name);
-        let exec = input.join(format!("{}.exe", &self.name));
+        let input = workspace.join(&executable);
+        let exec = input.join(&exe);
         let launcher = input.join("launch.bat");
         let icon = workspace.join("icon.ico");
         let rcedit = workspace.join("rcedit.exe");
@@ -92,8 +251,9 @@ impl Bundler for Windows<'_> {
             format!(
                 include_str!("../res/launch.bat"),
                 name = &self.name,
-                executable = format!("{}.exe", &self.name),
+                executable = &exe,
                 url = &self.url,
+                background = background_args(&self.colors),
             )
             .as_bytes(),
         )?;
@@ -108,7 +268,10 @@ impl Bundler for Windows<'_> {
             .arg("--output")
             .arg(bundle.to_string_lossy().as_ref())
             .output()?;
-        resize(&self.icon.img, 255, 255, Lanczos3).save(&icon)?;
+        match ico_verbatim(&self.icon) {
+            Some(data) => fs::write(&icon, data)?,
+            None => scale(&self.icon.img, ICO_SIZE, self.upscale).save(&icon)?,
+        }
         fs::File::create(&rcedit)?.write_all(include_bytes!("../res/rcedit.exe"))?;
         Command::new(&rcedit.to_string_lossy().as_ref())
             .arg("-open")
@@ -122,8 +285,24 @@ impl Bundler for Windows<'_> {
             .arg("-mask")
             .arg("ICONGROUP,1,1033")
             .output()?;
+        // Start menu tiles take the theme color, rather than the accent color
+        // of the user's desktop.
+        if let Some(theme) = self.colors.theme {
+            fs::write(
+                root.join(format!("{}.VisualElementsManifest.xml", &executable)),
+                format!(
+                    include_str!("../res/VisualElementsManifest.xml"),
+                    background = color::hex(theme),
+                    foreground = if color::luminance(theme) > 0.6 {
+                        "dark"
+                    } else {
+                        "light"
+                    },
+                ),
+            )?;
+        }
         // Cleanup.
-        fs::rename(&bundle, root.join(format!("{}.exe", &self.name)))?;
+        fs::rename(&bundle, root.join(&exe))?;
         fs::remove_dir_all(&workspace).map(|err| format!("removing temporary files: {:?}", err))?;
         Ok(())
     }
@@ -133,3 +312,60 @@ impl Bundler for Windows<'_> {
         Err("cannot bundle windows application on this OS".into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn names() {
+        for (name, expected_executable, expected_file_name) in &[
+            ("Google Mail", "googlemail", "Google Mail"),
+            ("AC/DC", "acdc", "AC-DC"),
+            ("München", "munchen", "München"),
+            ("C:\\Apps\\..", "capps..", "C--Apps-"),
+            ("..", "app", "app"),
+            ("東京", "xn--1lqs71d", "東京"),
+        ] {
+            assert_eq!(executable(name), *expected_executable, "{}", name);
+            assert_eq!(file_name(name), *expected_file_name, "{}", name);
+        }
+    }
+
+    #[test]
+    fn verbatim_icons() {
+        let icon = |mime: &str, side: u32| infer::Icon {
+            source: "https://example.com/icon".into(),
+            name: "icon".into(),
+            ext: "png".into(),
+            img: image::RgbaImage::new(side, side),
+            original: Some(infer::Original {
+                mime: mime.into(),
+                data: Arc::new(b"original".to_vec()),
+            }),
+        };
+        for (mime, side, icns, ico) in &[
+            ("image/icns", 1024, Some(&b"original"[..]), None),
+            ("image/icns", 512, Some(&b"original"[..]), None),
+            ("image/icns", 32, None, None),
+            ("image/png", 512, Some(&b"ic09"[..]), None),
+            ("image/png", 256, None, None),
+            ("image/png", 600, None, None),
+            ("image/x-icon", 256, None, Some(&b"original"[..])),
+            ("image/x-icon", 255, None, Some(&b"original"[..])),
+            ("image/x-icon", 48, None, None),
+            ("image/x-icon", 16, None, None),
+        ] {
+            let icon = icon(mime, *side);
+            // Wrapped pngs are identified by the type of their entry.
+            let wrapped = icns_verbatim(&icon);
+            let wrapped = wrapped.as_ref().map(|data| match *mime {
+                "image/png" => &data[8..12],
+                _ => &data[..],
+            });
+            assert_eq!(wrapped, *icns, "{} {}", mime, side);
+            assert_eq!(ico_verbatim(&icon), *ico, "{} {}", mime, side);
+        }
+    }
+}
@@ -2,21 +2,42 @@
 mod bundle;
 mod error;
 mod infer;
+mod report;
 
 use crate::bundle::Bundler;
-use crate::infer::{infer_icon, infer_name};
-use clap::{App, Arg, SubCommand};
+use crate::infer::auth::{self, Auth};
+use crate::infer::color::{self, Colors};
+use crate::infer::probe::SiteProbe;
+use crate::infer::record::{Recorder, Replay};
+use crate::infer::repository::{IconRepository, Location, Repository};
+use crate::infer::transform::{Transform, Upscale};
+use crate::infer::{cache, http, infer_name, Candidate, Downloader, Inferer};
+use clap::{App, AppSettings, Arg, SubCommand};
+use log::{warn, LevelFilter};
 use pretty_env_logger;
-use url::Url;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use url::{Host, Url};
 use web_view::*;
 
 fn main() {
     set_dpi_aware();
-    pretty_env_logger::init();
+    // Warnings, such as a download served from the cache, are shown unless
+    // RUST_LOG asks for something else.
+    let mut logger = pretty_env_logger::formatted_builder();
+    match env::var("RUST_LOG") {
+        Ok(filters) => logger.parse_filters(&filters),
+        Err(_) => logger.filter_level(LevelFilter::Warn),
+    };
+    logger.init();
     let matches = App::new("nativefier")
         .version("0.2.0")
         .author("Jack Mordaunt <jackmordaunt@gmail.com>")
         .about("Create native apps for your favourite site!")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("url")
                 .required(true)
@@ -30,6 +51,16 @@ fn main() {
                 .long("name")
                 .help("Name of app"),
         )
+        .arg(
+            Arg::with_name("name-from-page")
+                .long("name-from-page")
+                .help("Infer the name of the app from the site's markup rather than its hostname"),
+        )
+        .arg(
+            Arg::with_name("name-with-subdomain")
+                .long("name-with-subdomain")
+                .help("Include a meaningful subdomain in the inferred name, eg \"Google Mail\""),
+        )
         .arg(
             Arg::with_name("output")
                 .short("o")
@@ -42,25 +73,325 @@ fn main() {
                 .short("i")
                 .long("icon-override")
                 .takes_value(true)
-                .help("Alternative url to scrape the icon from"),
+                .help("Icon to use instead: a local image file, an image url or a page to scrape"),
+        )
+        .arg(
+            Arg::with_name("icon-repository")
+                .long("icon-repository")
+                .takes_value(true)
+                .help("Directory or url of an icon repository to look for curated icons in"),
+        )
+        .arg(
+            Arg::with_name("icon-padding")
+                .long("icon-padding")
+                .takes_value(true)
+                .help("Space to leave around the icon, as a percentage of its size"),
+        )
+        .arg(
+            Arg::with_name("icon-background")
+                .long("icon-background")
+                .takes_value(true)
+                .help("Color to put behind the icon, or \"auto\" to pick one from the site"),
+        )
+        .arg(
+            Arg::with_name("icon-mask")
+                .long("icon-mask")
+                .takes_value(true)
+                .possible_values(&["none", "circle", "rounded", "squircle"])
+                .help("Shape to cut the icon to"),
+        )
+        .arg(
+            Arg::with_name("icon-upscale")
+                .long("icon-upscale")
+                .takes_value(true)
+                .possible_values(&["auto", "smooth", "nearest", "pixel-art", "center"])
+                .help("How to enlarge small icons, defaults to auto"),
+        )
+        .arg(
+            Arg::with_name("min-icon-size")
+                .long("min-icon-size")
+                .takes_value(true)
+                .help("Warn when the icon found is smaller than this many pixels, defaults to 64"),
+        )
+        .arg(
+            Arg::with_name("no-icon-trim")
+                .long("no-icon-trim")
+                .help("Keep transparent borders around the icon"),
+        )
+        .arg(
+            Arg::with_name("no-cache")
+                .long("no-cache")
+                .help("Download the site and its icons without using the cache"),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .long("concurrency")
+                .takes_value(true)
+                .help("Number of icons to download at once, defaults to 8"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .help(
+                    "Seconds to wait for icons before settling for the best so far, defaults to 30",
+                ),
+        )
+        .arg(
+            Arg::with_name("request-timeout")
+                .long("request-timeout")
+                .takes_value(true)
+                .help("Seconds to wait for any single download, defaults to 30"),
+        )
+        .arg(
+            Arg::with_name("max-size")
+                .long("max-size")
+                .takes_value(true)
+                .help("Largest download allowed, in bytes, defaults to 10 MiB"),
+        )
+        .arg(
+            Arg::with_name("max-redirects")
+                .long("max-redirects")
+                .takes_value(true)
+                .help("Number of redirects to follow, defaults to 10"),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .takes_value(true)
+                .help("Number of times to retry a failed download, defaults to 2"),
+        )
+        .arg(
+            Arg::with_name("retry-backoff")
+                .long("retry-backoff")
+                .takes_value(true)
+                .help(
+                    "Milliseconds to wait before the first retry, doubled for each retry after it, defaults to 500",
+                ),
+        )
+        .arg(
+            Arg::with_name("user-agent")
+                .long("user-agent")
+                .takes_value(true)
+                .help("User-Agent to send with every request"),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .takes_value(true)
+                .help("Proxy to send requests through, instead of HTTP_PROXY and HTTPS_PROXY"),
+        )
+        .arg(
+            Arg::with_name("ca-cert")
+                .long("ca-cert")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("PEM file of root certificates to trust, such as a corporate CA"),
+        )
+        .arg(
+            Arg::with_name("insecure")
+                .long("insecure")
+                .help("Accept invalid certificates, such as self-signed intranet ones"),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Header to send to the site, as \"Name: value\""),
+        )
+        .arg(
+            Arg::with_name("cookie")
+                .long("cookie")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Cookie to send to the site, as name=value"),
+        )
+        .arg(
+            Arg::with_name("cookies-file")
+                .long("cookies-file")
+                .takes_value(true)
+                .help("Netscape cookies.txt file of cookies to send, as exported by a browser"),
+        )
+        .arg(
+            Arg::with_name("basic-auth")
+                .long("basic-auth")
+                .takes_value(true)
+                .help("Username and password to log into the site with, as user:password"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .conflicts_with("replay")
+                .help("Directory to record every download in, for replaying later"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .takes_value(true)
+                .help("Directory of recorded downloads to use instead of the network"),
+        )
+        .arg(
+            Arg::with_name("background-color")
+                .long("background-color")
+                .takes_value(true)
+                .hidden(true)
+                .help("Color shown while the site loads, set by the launch scripts of apps"),
         )
         .subcommand(
             SubCommand::with_name("inplace").about("Open the webview without creating an app"),
         )
+        .subcommand(
+            SubCommand::with_name("icons")
+                .about("List every icon candidate for a site")
+                .arg(
+                    Arg::with_name("url")
+                        .required(true)
+                        .takes_value(true)
+                        .help("Url of site to list icons for"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the candidates as json instead of a table"),
+                )
+                .arg(
+                    Arg::with_name("export")
+                        .long("export")
+                        .takes_value(true)
+                        .help("File to save a candidate to, as png, ico or icns"),
+                )
+                .arg(
+                    Arg::with_name("candidate")
+                        .long("candidate")
+                        .takes_value(true)
+                        .requires("export")
+                        .help("Number of the candidate to export, defaults to the best"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Manage the download cache")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("clear").about("Remove all cached downloads")),
+        )
         .get_matches();
-    let url: Url = match matches.value_of("url").unwrap().parse() {
-        Ok(url) => url,
-        Err(_) => format!("https://{}", matches.value_of("url").unwrap())
-            .parse()
-            .expect("malformed URL"),
+    if let ("cache", Some(cache_matches)) = matches.subcommand() {
+        match cache_matches.subcommand() {
+            ("clear", _) => cache::clear(&cache::dir()).expect("clearing cache"),
+            _ => unreachable!(),
+        }
+        return;
+    }
+    let mut options = http::Options {
+        timeout: seconds(matches.value_of("request-timeout"), 30),
+        ..http::Options::default()
+    };
+    if let Some(max_size) = matches.value_of("max-size") {
+        options.max_size = max_size.parse().expect("parsing max size");
+    }
+    if let Some(redirects) = matches.value_of("max-redirects") {
+        options.redirects = redirects.parse().expect("parsing max redirects");
+    }
+    if let Some(retries) = matches.value_of("retries") {
+        options.retries = retries.parse().expect("parsing retries");
+    }
+    if let Some(backoff) = matches.value_of("retry-backoff") {
+        options.backoff = Duration::from_millis(backoff.parse().expect("parsing retry backoff"));
+    }
+    if let Some(user_agent) = matches.value_of("user-agent") {
+        options.user_agent = user_agent.into();
+    }
+    if let Some(proxy) = matches.value_of("proxy") {
+        options.proxies.all(proxy);
+    }
+    options.ca_certs = matches
+        .values_of("ca-cert")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    options.insecure = matches.is_present("insecure");
+    let http = http::Client::new(options).expect("building http client");
+    let mut client: Arc<dyn Downloader + Send + Sync> = if matches.is_present("no-cache") {
+        Arc::new(http)
+    } else {
+        Arc::new(cache::Cache::new(http))
+    };
+    // Credentials are scoped to the site being nativefied. They're added above
+    // the cache, which doesn't store responses to requests that carry them.
+    let site = match matches.subcommand() {
+        ("icons", Some(icons_matches)) => icons_matches.value_of("url"),
+        _ => matches.value_of("url"),
+    }
+    .map(parse_url);
+    if let Some(site) = site {
+        let auth = authenticate(&site, &matches, client.clone());
+        if !auth.headers.is_empty() || !auth.cookies.is_empty() {
+            client = Arc::new(auth);
+        }
+    }
+    if let Some(dir) = matches.value_of("replay") {
+        client = Arc::new(Replay::open(Path::new(dir)).expect("opening recording"));
+    } else if let Some(dir) = matches.value_of("record") {
+        client = Arc::new(Recorder::new(Path::new(dir), client).expect("starting recording"));
+    }
+    let repository = matches.value_of("icon-repository").map(|location| {
+        Box::new(Repository {
+            location: Location::parse(location),
+            client: client.clone(),
+        }) as Box<dyn IconRepository + Send + Sync>
+    });
+    let mut inferer = Inferer::new(client);
+    inferer.repository = repository;
+    if let Some(concurrency) = matches.value_of("concurrency") {
+        inferer.concurrency = concurrency.parse().expect("parsing concurrency");
+    }
+    inferer.deadline = Some(seconds(matches.value_of("timeout"), 30));
+    if let ("icons", Some(icons_matches)) = matches.subcommand() {
+        icons(&inferer, icons_matches);
+        return;
+    }
+    let url = match matches.value_of("url") {
+        Some(url) => url,
+        None => clap::Error::with_description(
+            "The following required arguments were not provided:\n    <url>",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
     };
+    let url = parse_url(url);
+    let transform = transform(&matches);
+    let name_from_page = matches.value_of("name").is_none() && matches.is_present("name-from-page");
+    // The name, icon and colors share a single fetch of the page.
+    let probe = if name_from_page || matches.subcommand_name() != Some("inplace") {
+        Some(
+            inferer
+                .probe(url.as_str())
+                .map_err(|err| warn!("probing {}: {}", url, err))
+                .ok(),
+        )
+    } else {
+        None
+    };
+    let subdomain = matches.is_present("name-with-subdomain");
     let name: String = match matches.value_of("name") {
         Some(name) => name.into(),
-        None => infer_name(&url).expect("inferring name"),
+        None if name_from_page => probe
+            .as_ref()
+            .and_then(Option::as_ref)
+            .and_then(SiteProbe::name)
+            .map_or_else(|| infer_name(&url, subdomain), Ok)
+            .expect("inferring name"),
+        None => infer_name(&url, subdomain).expect("inferring name"),
     };
     match matches.subcommand() {
         ("inplace", _) => {
-            let wv = web_view::builder()
+            let mut wv = web_view::builder()
                 .title(&name)
                 .content(Content::Url(&url))
                 .size(800, 600)
@@ -70,21 +401,52 @@ fn main() {
                 .invoke_handler(|_wv, _arg| Ok(()))
                 .build()
                 .expect("building webview");
+            if let Some(background) = matches.value_of("background-color").and_then(color::parse) {
+                let [r, g, b, _] = background.0;
+                wv.set_color((r, g, b));
+            }
             wv.run().expect("running webview");
         }
         _ => {
             let dir = matches.value_of("output").unwrap_or("");
-            let icon_url: Url = match matches.value_of("icon-override") {
-                Some(icon_url) => icon_url.parse().expect("malformed URL"),
-                None => url.clone(),
-            };
-            let icon = infer_icon(&icon_url).expect("inferring icon");
+            let mut icon = match matches.value_of("icon-override") {
+                Some(source) => inferer.load(source),
+                None => match &probe {
+                    Some(probe) => inferer.infer_from(&url, probe.as_ref()),
+                    None => inferer.infer(url.as_str()),
+                },
+            }
+            .expect("inferring icon");
+            let min_size: u32 = matches
+                .value_of("min-icon-size")
+                .map_or(64, |size| size.parse().expect("parsing min icon size"));
+            let (width, height) = icon.img.dimensions();
+            if width.min(height) < min_size {
+                warn!(
+                    "the best icon for {} is only {}x{}, smaller than {}x{}",
+                    url, width, height, min_size, min_size
+                );
+            }
+            let probe = probe.as_ref().and_then(Option::as_ref);
+            // Sites that declare no colors take them from their icon, before
+            // it's padded onto a background.
+            let colors = probe
+                .map_or_else(Colors::default, |probe| probe.colors)
+                .or_dominant(&icon.img);
+            transform.apply(&mut icon, probe.and_then(SiteProbe::theme_color));
+            let upscale = matches
+                .value_of("icon-upscale")
+                .map_or(Upscale::Auto, |upscale| {
+                    upscale.parse().expect("parsing icon upscaling")
+                });
             if cfg!(windows) {
                 bundle::Windows {
                     dir: &dir,
                     name: &name,
                     url: &url,
                     icon: icon,
+                    colors,
+                    upscale,
                 }
                 .bundle()
                 .expect("bundling Windows app");
@@ -94,6 +456,8 @@ fn main() {
                     name: &name,
                     url: &url,
                     icon: icon,
+                    colors,
+                    upscale,
                 }
                 .bundle()
                 .expect("bundling MacOS app");
@@ -102,6 +466,118 @@ fn main() {
     };
 }
 
+/// List the icon candidates for a site, exporting one if asked to.
+fn icons<D>(inferer: &Inferer<D>, matches: &clap::ArgMatches)
+where
+    D: Downloader + Clone + Send + Sync + 'static,
+{
+    let url = parse_url(matches.value_of("url").expect("url is required"));
+    let candidates = inferer
+        .candidates(url.as_str())
+        .expect("listing icon candidates");
+    if matches.is_present("json") {
+        println!("{:#}", report::json(&candidates));
+    } else {
+        print!("{}", report::table(&candidates));
+    }
+    if let Some(path) = matches.value_of("export") {
+        let number: usize = matches
+            .value_of("candidate")
+            .map_or(1, |n| n.parse().expect("parsing candidate number"));
+        let icon = match candidates.get(number.wrapping_sub(1)) {
+            Some(Candidate {
+                icon: Some(icon), ..
+            }) => icon,
+            Some(_) => panic!("candidate {} has no icon", number),
+            None => panic!("there is no candidate {}", number),
+        };
+        report::export(icon, Path::new(path)).expect("exporting icon");
+    }
+}
+
+/// Gather the icon transform given on the command line.
+fn transform(matches: &clap::ArgMatches) -> Transform {
+    let mut transform = Transform {
+        trim: !matches.is_present("no-icon-trim"),
+        ..Transform::default()
+    };
+    if let Some(padding) = matches.value_of("icon-padding") {
+        let percent: f32 = padding
+            .trim_end_matches('%')
+            .parse()
+            .expect("parsing icon padding");
+        transform.padding = percent / 100.0;
+    }
+    if let Some(background) = matches.value_of("icon-background") {
+        transform.background = background.parse().expect("parsing icon background");
+    }
+    if let Some(mask) = matches.value_of("icon-mask") {
+        transform.mask = mask.parse().expect("parsing icon mask");
+    }
+    transform
+}
+
+/// Gather the credentials given on the command line for the site at url.
+fn authenticate<D: Downloader>(url: &Url, matches: &clap::ArgMatches, inner: D) -> Auth<D> {
+    let mut auth = Auth::new(url.host_str().unwrap_or_default(), inner);
+    for header in matches.values_of("header").into_iter().flatten() {
+        let (name, value) = auth::header(header).expect("parsing header");
+        auth.headers.append(name, value);
+    }
+    for cookie in matches.values_of("cookie").into_iter().flatten() {
+        let mut parts = cookie.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => auth.cookie(name.trim(), value.trim()),
+            _ => panic!("parsing cookie {:?}: expected name=value", cookie),
+        }
+    }
+    if let Some(path) = matches.value_of("cookies-file") {
+        let text = fs::read_to_string(path).expect("reading cookies file");
+        auth.cookies.extend(auth::cookies_txt(&text));
+    }
+    if let Some(credentials) = matches.value_of("basic-auth") {
+        let mut parts = credentials.splitn(2, ':');
+        let user = parts.next().unwrap_or_default();
+        auth.basic(user, parts.next().unwrap_or_default())
+            .expect("setting basic auth");
+    }
+    auth
+}
+
+/// Parse the url of the site to nativefy. Urls without a scheme are assumed to
+/// be https, except for local addresses such as "localhost:3000" or
+/// "192.168.1.10", which are assumed to be development servers using http.
+/// Local addresses are loopback, private and link local ones.
+fn parse_url(url: &str) -> Url {
+    match url.parse::<Url>() {
+        Ok(parsed) if parsed.has_host() || parsed.scheme() == "file" => parsed,
+        _ => {
+            let mut parsed: Url = format!("https://{}", url).parse().expect("malformed URL");
+            let local = match parsed.host() {
+                Some(Host::Domain(host)) => host == "localhost",
+                Some(Host::Ipv4(addr)) => {
+                    addr.is_loopback() || addr.is_private() || addr.is_link_local()
+                }
+                // Unique local (fc00::/7) and link local (fe80::/10) addresses.
+                Some(Host::Ipv6(addr)) => {
+                    let first = addr.segments()[0];
+                    addr.is_loopback() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+                }
+                None => false,
+            };
+            if local {
+                parsed.set_scheme("http").expect("setting url scheme");
+            }
+            parsed
+        }
+    }
+}
+
+/// Parse a number of seconds given on the command line.
+fn seconds(value: Option<&str>, default: u64) -> Duration {
+    Duration::from_secs(value.map_or(default, |v| v.parse().expect("parsing seconds")))
+}
+
 #[cfg(target_os = "windows")]
 fn set_dpi_aware() {
     use winapi::um::shellscalingapi::{SetProcessDpiAwareness, PROCESS_SYSTEM_DPI_AWARE};
@@ -110,3 +586,30 @@ fn set_dpi_aware() {
 
 #[cfg(not(target_os = "windows"))]
 fn set_dpi_aware() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        for (url, expected) in &[
+            ("https://example.com", "https://example.com/"),
+            ("example.com", "https://example.com/"),
+            ("localhost:3000", "http://localhost:3000/"),
+            ("127.0.0.1:8080", "http://127.0.0.1:8080/"),
+            ("192.168.1.10", "http://192.168.1.10/"),
+            ("10.0.0.2/app", "http://10.0.0.2/app"),
+            ("169.254.0.1", "http://169.254.0.1/"),
+            ("[::1]:8080", "http://[::1]:8080/"),
+            ("[fd00::1]", "http://[fd00::1]/"),
+            ("[fe80::1]", "http://[fe80::1]/"),
+            // Public addresses aren't assumed to be development servers.
+            ("8.8.8.8", "https://8.8.8.8/"),
+            ("[2001:db8::1]", "https://[2001:db8::1]/"),
+            ("file:///tmp/site/index.html", "file:///tmp/site/index.html"),
+        ] {
+            assert_eq!(parse_url(url).as_str(), *expected, "{}", url);
+        }
+    }
+}
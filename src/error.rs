@@ -15,6 +15,8 @@ pub enum Error {
     Image(image::ImageError),
     /// Scraping markup for icons.
     Scrape(String),
+    /// Reading an icon repository.
+    Repository(String),
     // InferName captures errors that occur while trying to infer app name from
     // a url.
     InferName {
@@ -37,6 +39,7 @@ impl std::fmt::Display for Error {
             Error::Download(err) => write!(f, "downloading: {}", err),
             Error::Image(err) => write!(f, "image: {}", err),
             Error::Scrape(s) => write!(f, "scraping: {}", s),
+            Error::Repository(s) => write!(f, "icon repository: {}", s),
             Error::InferName { url, reason } => write!(f, "inferring name for {}: {}", url, reason),
         }
     }
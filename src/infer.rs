@@ -1,19 +1,54 @@
 use crate::error::{Error, ParseError};
 use image;
-use log::debug;
+use image::Rgba;
+use log::{debug, warn};
 use reqwest;
-use scraper::{Html, Selector};
+use reqwest::header::{HeaderMap, HeaderName, CONTENT_TYPE};
+use reqwest::StatusCode;
+use scraper::Selector;
 use std::cmp::{Ord, Ordering, PartialEq, PartialOrd};
+use std::collections::VecDeque;
 use std::convert::*;
+use std::fs;
 use std::io::{copy, Read};
+use std::path::Path;
 use std::result::Result as StdResult;
-use std::sync::mpsc::channel;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 use url::Url;
 
+use self::manifest::Manifest;
+use self::page::Page;
+use self::probe::SiteProbe;
+use self::repository::IconRepository;
+
+pub mod auth;
+pub mod cache;
+pub mod color;
+pub mod data;
+mod decode;
+pub mod fallback;
+pub mod http;
+pub mod manifest;
+pub mod name;
+pub mod page;
+pub mod probe;
+pub mod psl;
+pub mod record;
+pub mod repository;
+#[cfg(test)]
+pub mod tempdir;
+pub mod tile;
+pub mod transform;
+
 pub type Result<T> = StdResult<T, Error>;
 
+/// Number of `<meta http-equiv="refresh">` hops followed from a page.
+const MAX_REFRESHES: usize = 5;
+
 /// Infer an icon using the default Inferer.
 pub fn infer_icon(url: &Url) -> Result<Icon> {
     Inferer::default().infer(&url.clone().into_string())
@@ -26,26 +61,29 @@ pub fn infer_icon(url: &Url) -> Result<Icon> {
 ///
 /// This is quicker (no io), but doesn't allow for "pretty" titles (with capital
 /// letters, whitespace, etc).
-pub fn infer_name(url: &Url) -> Result<String> {
-    let host = match url.host_str() {
-        Some(host) => host,
-        None => {
-            return Err(Error::InferName {
-                url: url.clone(),
-                reason: "url does not include hostname".into(),
-            })
-        }
-    };
-    // If there is two dots eg www.example.com, take the middle part "example"
-    // as the name.
-    // If there is one dot eg soundcloud.com, take the first part "soundcloud"
-    // as the name.
-    match host.matches(".").count() {
-        1 => Ok(host.split(".").nth(0).unwrap().into()),
-        2 => Ok(host.split(".").nth(1).unwrap().into()),
-        _ => Err(Error::InferName {
+///
+/// Use `SiteProbe::name` for pretty titles taken from the site's markup.
+///
+/// The name is taken from the registrable domain, as determined by the Public
+/// Suffix List, so "www.bbc.co.uk" becomes "bbc". With subdomain set, a
+/// meaningful subdomain is included: "mail.google.com" becomes "Google Mail".
+/// Local and intranet sites are named after their host and port instead, and
+/// files after their directory.
+///
+/// Internationalized domain names are decoded, so the name is meant for
+/// display; see `bundle::executable` for a name that is safe to use on disk.
+pub fn infer_name(url: &Url, subdomain: bool) -> Result<String> {
+    if url.scheme() != "file" && url.host().is_none() {
+        return Err(Error::InferName {
+            url: url.clone(),
+            reason: "url does not include hostname".into(),
+        });
+    }
+    match name::from_url(&psl::List::embedded(), url, subdomain) {
+        Some(name) => Ok(name),
+        None => Err(Error::InferName {
             url: url.clone(),
-            reason: "url contains an uncommon hostname format".into(),
+            reason: "url has no registrable domain or directory".into(),
         }),
     }
 }
@@ -54,121 +92,542 @@ pub fn infer_name(url: &Url) -> Result<String> {
 pub struct Inferer<D: Downloader> {
     /// client downloads the icon data into a buffer.
     pub client: D,
+    /// repository is consulted for a curated icon before scraping.
+    pub repository: Option<Box<dyn IconRepository + Send + Sync>>,
+    /// Maximum number of icons downloaded at once.
+    pub concurrency: usize,
+    /// Time allowed for downloading icons. Once it passes, the icons that have
+    /// arrived are chosen from and the rest are abandoned.
+    pub deadline: Option<Duration>,
+    /// Set to abandon any icon downloads still in progress, as if the deadline
+    /// had passed. Meant to be set from another thread, such as a UI's cancel
+    /// button, while an inference runs; it stays set until cleared.
+    pub cancel: Arc<AtomicBool>,
 }
 
-/// Default to using reqwest crate to perform network calls.
-impl Inferer<reqwest::Client> {
-    fn default() -> Inferer<reqwest::Client> {
+impl<D: Downloader> Inferer<D> {
+    pub fn new(client: D) -> Inferer<D> {
         Inferer {
-            client: reqwest::ClientBuilder::new().build().unwrap(),
+            client,
+            repository: None,
+            concurrency: 8,
+            deadline: Some(Duration::from_secs(30)),
+            cancel: Arc::new(AtomicBool::new(false)),
         }
     }
 }
 
+/// Default to using reqwest crate to perform network calls, caching responses
+/// on disk between runs.
+impl Default for Inferer<cache::Cache<http::Client>> {
+    fn default() -> Inferer<cache::Cache<http::Client>> {
+        Inferer::new(cache::Cache::new(
+            http::Client::new(http::Options::default()).unwrap(),
+        ))
+    }
+}
+
 /// infer the best icon for a url by downloading icon links and comparing for
 /// size, preferring the largest.
 impl<D> Inferer<D>
 where
     D: Downloader + Clone + Send + Sync + 'static,
 {
-    fn infer(&self, url: &str) -> Result<Icon> {
-        let (tx, tr) = channel();
-        let client = Arc::new(self.client.clone());
-        let mut workers = vec![];
-        for link in self.scrape(url)? {
-            let client = client.clone();
-            let tx = tx.clone();
-            workers.push(thread::spawn(move || {
-                let icon = match Icon::download(client.as_ref(), &link) {
-                    Ok(icon) => Some(icon),
-                    Err(err) => {
-                        debug!("downloading icon: {}", err);
-                        None
-                    }
-                };
-                tx.send(icon).expect("sending icon over channel");
-            }));
+    /// Infer the best icon for the site at url.
+    ///
+    /// If the site has no usable icon, one is generated from its name.
+    pub fn infer(&self, url: &str) -> Result<Icon> {
+        let parsed = Url::parse(url)?;
+        if let Some(icon) = self.lookup(&parsed) {
+            return Ok(icon);
         }
-        let mut icons = vec![];
-        for _ in workers {
-            if let Some(icon) = tr.recv().expect("receiving icon from channel") {
-                icons.push(icon);
+        let probe = self
+            .probe(url)
+            .map_err(|err| warn!("scraping {}: {}", url, err))
+            .ok();
+        self.choose_or_generate(&parsed, probe.as_ref())
+    }
+    /// Infer the best icon for the site at url, like `infer`, but from a probe
+    /// of the site already made for other inferences. Without a probe, as when
+    /// probing failed, the icon comes from the repository or is generated.
+    pub fn infer_from(&self, url: &Url, probe: Option<&SiteProbe>) -> Result<Icon> {
+        if let Some(icon) = self.lookup(url) {
+            return Ok(icon);
+        }
+        self.choose_or_generate(url, probe)
+    }
+    /// Look up a curated icon for url in the repository, if there is one.
+    fn lookup(&self, url: &Url) -> Option<Icon> {
+        let repository = self.repository.as_ref()?;
+        match repository.lookup(url) {
+            Ok(Some(icon)) => return Some(icon),
+            Ok(None) => debug!("icon repository has no icon for {}", url),
+            Err(err) => warn!("looking up icon repository: {}", err),
+        }
+        None
+    }
+    /// Choose the best icon linked from the probed site, generating one from
+    /// the site's name if it has no usable icon.
+    fn choose_or_generate(&self, url: &Url, probe: Option<&SiteProbe>) -> Result<Icon> {
+        let links = probe.map_or_else(Vec::new, |probe| probe.links.clone());
+        match self.choose(links) {
+            Ok(icon) => Ok(icon),
+            Err(err) => {
+                warn!("{}: generating an icon instead", err);
+                let host = url.host_str().unwrap_or_default();
+                let name = infer_name(url, false).unwrap_or_else(|_| host.into());
+                Ok(fallback::generate(
+                    &name,
+                    host,
+                    probe.and_then(SiteProbe::theme_color),
+                ))
             }
         }
-        icons.sort();
-        match icons.into_iter().last() {
+    }
+    /// Fetch the page at url once, along with its manifest and browserconfig,
+    /// and gather what they say about the site.
+    pub fn probe(&self, url: &str) -> Result<SiteProbe> {
+        let page = self.page(url)?;
+        let manifest = self.page_manifest(&page);
+        let links = self.links(&page);
+        Ok(SiteProbe::new(page, manifest, links))
+    }
+    /// Load an icon from source, which may be a local image file, a direct
+    /// link to an image, or a page to scrape for icons.
+    pub fn load(&self, source: &str) -> Result<Icon> {
+        if Path::new(source).is_file() {
+            return Icon::open(Path::new(source));
+        }
+        let url = match Url::parse(source) {
+            Ok(url) => url,
+            Err(url::ParseError::RelativeUrlWithoutBase) => {
+                Url::parse(&format!("https://{}", source))?
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if url.scheme() == "file" {
+            return match url.to_file_path() {
+                Ok(path) => Icon::open(&path),
+                Err(_) => Err(Error::Scrape(format!("invalid file url: {}", url))),
+            };
+        }
+        if url.scheme() == "data" {
+            return Icon::download(&self.client, source);
+        }
+        let mut response = self
+            .client
+            .fetch(Request::new(url.as_str()))?
+            .error_for_status()?;
+        let mut data = vec![];
+        response.body.read_to_end(&mut data)?;
+        let is_html = response
+            .content_type()
+            .map_or(false, |v| v.contains("html"));
+        if !is_html {
+            match Icon::decode(&response.url, &data) {
+                Ok(icon) => return Ok(icon),
+                Err(err) => debug!("decoding {} as an image: {}", &response.url, err),
+            }
+        }
+        let markup = page::decode(response.header(CONTENT_TYPE), &data);
+        let page = Page::parse(Url::parse(&response.url)?, &markup);
+        let page = self.follow(page)?;
+        self.choose(self.links(&page))
+    }
+    /// Download each icon link, choosing the largest icon.
+    fn choose(&self, links: Vec<Link>) -> Result<Icon> {
+        let best = self
+            .download(links)
+            .into_iter()
+            .max_by(Candidate::rank)
+            .and_then(|candidate| candidate.icon);
+        match best {
             Some(icon) => Ok(icon),
             None => Err(Error::Scrape("no icons found".into())),
         }
     }
-    /// Scrape icon links form the html markup at the given url.
-    // FIXME: - Should we return stronger types, like Vec<Url>?
-    //        - Should the scraping errors simply be ignored? They would only
-    //          be useful for debugging, not for users, so how to expose for
-    //          debugging?
-    fn scrape(&self, url: &str) -> Result<Vec<String>> {
-        let mut body = self.client.get(url)?;
-        let mut buf = String::new();
-        body.read_to_string(&mut buf)?;
-        let doc = Html::parse_document(&buf);
-        let link_el = Selector::parse("link").unwrap();
-        let base = Url::parse(url)?;
-        let links: Vec<String> = doc
-            .select(&link_el)
-            .map(|el| {
-                let el = el.value();
-                let rel = match el.attr("rel") {
-                    Some(rel) => rel,
-                    None => return Err(Error::Scrape("no rel attribute on link element".into())),
-                };
-                let href = match el.attr("href") {
-                    Some(href) => href,
-                    None => return Err(Error::Scrape("no href attribute on link element".into())),
-                };
-                if !rel.contains("icon") {
-                    return Err(Error::Scrape(
-                        "'rel' attribute does not include 'icon'".into(),
-                    ));
+    /// List every icon candidate for the site at url, best first, including
+    /// those that failed to download or decode.
+    pub fn candidates(&self, url: &str) -> Result<Vec<Candidate>> {
+        let probe = self.probe(url)?;
+        let mut candidates = self.download(probe.links);
+        candidates.sort_by(|a, b| b.rank(a));
+        Ok(candidates)
+    }
+    /// Download each icon link, giving a candidate for each image found and
+    /// for each link that failed.
+    ///
+    /// Links are downloaded by a pool of at most `concurrency` workers. When
+    /// the deadline passes or the inferer is cancelled, workers stop picking
+    /// up links and the links still outstanding are reported as failed.
+    /// Workers blocked on a download are left to finish in the background.
+    fn download(&self, links: Vec<Link>) -> Vec<Candidate> {
+        let started = Instant::now();
+        let expected = links.len();
+        let queue = Arc::new(Mutex::new(
+            links.iter().cloned().enumerate().collect::<VecDeque<_>>(),
+        ));
+        let (tx, tr) = channel();
+        let client = Arc::new(self.client.clone());
+        // Stops the workers of this call, without cancelling the inferer.
+        let stop = Arc::new(AtomicBool::new(false));
+        for _ in 0..self.concurrency.max(1).min(expected) {
+            let client = client.clone();
+            let queue = queue.clone();
+            let (stop, cancel) = (stop.clone(), self.cancel.clone());
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                if stop.load(AtomicOrdering::SeqCst) || cancel.load(AtomicOrdering::SeqCst) {
+                    break;
                 }
-                Ok(href.into())
-            })
-            .map(|r: Result<String>| match r {
-                Ok(link) => link,
-                Err(err) => {
-                    debug!("malformed link: {}", err);
-                    "".into()
+                let (index, link) = match queue.lock().expect("locking link queue").pop_front() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let mut icons = Icon::download_all(client.as_ref(), &link.href);
+                if let (Ok(icons), Some(color)) = (&mut icons, link.color) {
+                    for icon in icons.iter_mut() {
+                        color::tint(&mut icon.img, color);
+                        icon.original = None;
+                    }
                 }
-            })
-            .map(|link: String| {
-                if link.contains("http") {
-                    return link;
+                if tx.send((index, icons)).is_err() {
+                    break;
                 }
-                match base.join(&link) {
-                    Ok(url) => url.into_string(),
-                    Err(err) => {
-                        debug!("joining {} to {}: {}", &link, &base, err);
-                        "".into()
+            });
+        }
+        drop(tx);
+        let mut results: Vec<Option<Result<Vec<Icon>>>> = links.iter().map(|_| None).collect();
+        let mut received = 0;
+        let outstanding = loop {
+            if received == expected {
+                break "";
+            }
+            if self.cancel.load(AtomicOrdering::SeqCst) {
+                warn!("cancelled with {} icons outstanding", expected - received);
+                break "cancelled";
+            }
+            // Wake up periodically to notice cancellation.
+            let mut wait = Duration::from_millis(100);
+            if let Some(deadline) = self.deadline {
+                match deadline.checked_sub(started.elapsed()) {
+                    Some(remaining) => wait = wait.min(remaining),
+                    None => {
+                        warn!(
+                            "gave up waiting for {} icons after {:?}",
+                            expected - received,
+                            deadline
+                        );
+                        break "not downloaded before the deadline";
                     }
                 }
-            })
-            .filter(|link: &String| !link.is_empty())
-            .collect();
-        Ok(links)
+            }
+            match tr.recv_timeout(wait) {
+                Ok((index, icons)) => {
+                    received += 1;
+                    results[index] = Some(icons);
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break "abandoned",
+            }
+        };
+        stop.store(true, AtomicOrdering::SeqCst);
+        let mut candidates = vec![];
+        for (link, result) in links.into_iter().zip(results) {
+            match result {
+                Some(Ok(icons)) => candidates.extend(icons.into_iter().map(|icon| Candidate {
+                    link: link.clone(),
+                    icon: Some(icon),
+                    error: None,
+                })),
+                Some(Err(err)) => {
+                    debug!("downloading icon {}: {}", &link.href, err);
+                    candidates.push(Candidate {
+                        link,
+                        icon: None,
+                        error: Some(err.to_string()),
+                    });
+                }
+                None => candidates.push(Candidate {
+                    link,
+                    icon: None,
+                    error: Some(outstanding.into()),
+                }),
+            }
+        }
+        candidates
+    }
+    /// Find every icon link for page, from its markup and its browserconfig
+    /// file.
+    fn links(&self, page: &Page) -> Vec<Link> {
+        let mut found = links(page);
+        if let Some(config) = tile::config(page) {
+            match self.browserconfig(&config) {
+                Ok(logos) => found.extend(logos),
+                Err(err) => debug!("loading browserconfig {}: {}", config, err),
+            }
+        }
+        found
+    }
+    /// Download the browserconfig file at url and extract its logos.
+    fn browserconfig(&self, url: &str) -> Result<Vec<Link>> {
+        let mut response = self.client.fetch(Request::new(url))?.error_for_status()?;
+        let mut buf = vec![];
+        response.body.read_to_end(&mut buf)?;
+        Ok(tile::logos(
+            &Url::parse(&response.url)?,
+            &String::from_utf8_lossy(&buf),
+        ))
+    }
+    /// Load the web app manifest linked from page, if there is a usable one.
+    fn page_manifest(&self, page: &Page) -> Option<Manifest> {
+        let manifest_el = Selector::parse(r#"link[rel="manifest"]"#).unwrap();
+        let href = page
+            .doc
+            .select(&manifest_el)
+            .filter_map(|el| el.value().attr("href"))
+            .filter_map(|href| page.resolve(href))
+            .next()?;
+        self.manifest(&href)
+            .map_err(|err| debug!("loading manifest {}: {}", href, err))
+            .ok()
+    }
+    /// Download and parse the web app manifest at url.
+    fn manifest(&self, url: &str) -> Result<Manifest> {
+        let mut buf = vec![];
+        self.client.get(url)?.read_to_end(&mut buf)?;
+        Manifest::parse(&buf).map_err(|err| Error::Scrape(format!("parsing manifest: {}", err)))
+    }
+    /// Download the page at url, following any `<meta http-equiv="refresh">`
+    /// to the page it lands on.
+    fn page(&self, url: &str) -> Result<Page> {
+        let page = self.fetch_page(url)?;
+        self.follow(page)
+    }
+    fn fetch_page(&self, url: &str) -> Result<Page> {
+        let mut response = self.client.fetch(Request::new(url))?.error_for_status()?;
+        let mut buf = vec![];
+        response.body.read_to_end(&mut buf)?;
+        let markup = page::decode(response.header(CONTENT_TYPE), &buf);
+        Ok(Page::parse(Url::parse(&response.url)?, &markup))
+    }
+    /// Follow the refreshes of page, giving up on long chains rather than
+    /// looping forever.
+    fn follow(&self, mut page: Page) -> Result<Page> {
+        for _ in 0..MAX_REFRESHES {
+            match page.refresh() {
+                Some(target) => {
+                    debug!("following refresh from {} to {}", &page.url, &target);
+                    page = self.fetch_page(&target)?;
+                }
+                None => return Ok(page),
+            }
+        }
+        Err(Error::Scrape(format!(
+            "more than {} refreshes from {}",
+            MAX_REFRESHES, &page.url
+        )))
+    }
+}
+
+/// Link is an icon linked from a page.
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// Absolute url of the icon.
+    pub href: String,
+    /// Relationship of the icon to the page, such as "apple-touch-icon".
+    pub rel: String,
+    /// Sizes the page declares the icon to have, such as "32x32".
+    pub sizes: Option<String>,
+    /// Color to tint a monochrome mask icon with.
+    pub color: Option<Rgba<u8>>,
+}
+
+impl Link {
+    /// Whether the link is only worth using when a site has no other icon.
+    ///
+    /// Mask icons and tile images are monochrome shapes meant to be drawn in
+    /// a single color. Being large, or vectors rasterized at a large size,
+    /// they'd otherwise beat a site's full color icons.
+    pub fn is_last_resort(&self) -> bool {
+        self.color.is_some() || tile::is_tile(&self.rel)
+    }
+}
+
+/// Candidate is an icon considered for a site, or the reason a link couldn't
+/// be used.
+#[derive(Debug)]
+pub struct Candidate {
+    pub link: Link,
+    pub icon: Option<Icon>,
+    pub error: Option<String>,
+}
+
+impl Candidate {
+    /// Compare candidates by how good an icon they give: any icon beats a
+    /// failure, full color icons beat last resorts, and then icons are
+    /// ranked as `Icon` ranks them.
+    fn rank(&self, other: &Candidate) -> Ordering {
+        let priority =
+            |candidate: &Candidate| (candidate.icon.is_some(), !candidate.link.is_last_resort());
+        priority(self)
+            .cmp(&priority(other))
+            .then_with(|| self.icon.cmp(&other.icon))
     }
 }
 
+/// Extract icon links from a page, resolved to absolute urls.
+fn links(page: &Page) -> Vec<Link> {
+    let link_el = Selector::parse("link").unwrap();
+    page.doc
+        .select(&link_el)
+        .map(|el| {
+            let el = el.value();
+            let rel = match el.attr("rel") {
+                Some(rel) => rel,
+                None => return Err(Error::Scrape("no rel attribute on link element".into())),
+            };
+            let href = match el.attr("href") {
+                Some(href) => href,
+                None => return Err(Error::Scrape("no href attribute on link element".into())),
+            };
+            if !rel.contains("icon") {
+                return Err(Error::Scrape(
+                    "'rel' attribute does not include 'icon'".into(),
+                ));
+            }
+            // Safari's pinned tab icons are monochrome, drawn in the color
+            // the page declares, or black.
+            let color = if rel
+                .split_whitespace()
+                .any(|r| r.eq_ignore_ascii_case("mask-icon"))
+            {
+                Some(
+                    el.attr("color")
+                        .and_then(color::parse)
+                        .unwrap_or(Rgba([0, 0, 0, 255])),
+                )
+            } else {
+                None
+            };
+            match page.resolve(href) {
+                Some(href) => Ok(Link {
+                    href,
+                    rel: rel.into(),
+                    sizes: el.attr("sizes").map(String::from),
+                    color,
+                }),
+                None => Err(Error::Scrape(format!("cannot resolve {}", href))),
+            }
+        })
+        .filter_map(|r: Result<Link>| match r {
+            Ok(link) => Some(link),
+            Err(err) => {
+                debug!("malformed link: {}", err);
+                None
+            }
+        })
+        .chain(tile::image(page))
+        .collect()
+}
+
 /// Downloader performs network requests.
 /// The default Downloader uses reqwest crate.
 pub trait Downloader {
-    fn get(&self, url: &str) -> Result<Box<dyn Read>>;
+    /// Perform a GET request, returning the response whatever its status.
+    fn fetch(&self, req: Request) -> Result<Response>;
+    /// Download the body at url, failing unless the response is a success.
+    fn get(&self, url: &str) -> Result<Box<dyn Read>> {
+        Ok(self.fetch(Request::new(url))?.error_for_status()?.body)
+    }
 }
 
 impl Downloader for reqwest::Client {
-    fn get(&self, url: &str) -> Result<Box<dyn Read>> {
-        Ok(Box::new(reqwest::Client::get(self, url).send()?))
+    fn fetch(&self, req: Request) -> Result<Response> {
+        let response = reqwest::Client::get(self, &req.url)
+            .headers(req.headers)
+            .send()?;
+        Ok(Response {
+            url: response.url().as_str().into(),
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: Box::new(response),
+        })
+    }
+}
+
+/// Shared Downloaders, allowing the concrete Downloader to be chosen at
+/// runtime.
+impl<D> Downloader for Arc<D>
+where
+    D: Downloader + ?Sized,
+{
+    fn fetch(&self, req: Request) -> Result<Response> {
+        self.as_ref().fetch(req)
+    }
+}
+
+/// Request describes a GET request made by a Downloader.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// Url to request.
+    pub url: String,
+    /// Additional headers to send, such as validators for conditional
+    /// requests.
+    pub headers: HeaderMap,
+    /// Whether headers carry credentials, so that the response may be
+    /// private to the user and mustn't be cached.
+    pub credentials: bool,
+}
+
+impl Request {
+    pub fn new(url: &str) -> Request {
+        Request {
+            url: url.into(),
+            headers: HeaderMap::new(),
+            credentials: false,
+        }
     }
 }
 
+/// Response is the result of a Request.
+pub struct Response {
+    /// Final url of the response, after any redirects.
+    pub url: String,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Box<dyn Read>,
+}
+
+impl Response {
+    /// Turn an unsuccessful response, such as a 404 page, into an error.
+    pub fn error_for_status(self) -> Result<Response> {
+        if self.status.is_success() {
+            Ok(self)
+        } else {
+            Err(Error::Download(
+                format!("{}: status {}", self.url, self.status).into(),
+            ))
+        }
+    }
+
+    /// Value of the header called name, if it's present and readable as text.
+    pub fn header(&self, name: HeaderName) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+
+    /// Media type of the body, without parameters such as the charset.
+    pub fn content_type(&self) -> Option<String> {
+        let value = self.header(CONTENT_TYPE)?;
+        let media = value.split(';').next().unwrap_or_default().trim();
+        Some(media.to_lowercase())
+    }
+}
+
+/// Content types that icons are never served as. Anything else is given to the
+/// decoders, since icons are often served with generic or wrong types.
+const NOT_IMAGES: &[&str] = &["html", "json", "javascript", "css"];
+
 /// Icon is icon detected for a website.
 #[derive(Debug)]
 pub struct Icon {
@@ -180,30 +639,151 @@ pub struct Icon {
     pub ext: String,
     /// Container for the image data.
     pub img: image::RgbaImage,
+    /// File the image was decoded from, if img still matches it.
+    pub original: Option<Original>,
+}
+
+/// Original is an encoded image file, kept so that it can be embedded as is
+/// rather than re-encoded from its decoded pixels.
+#[derive(Debug, Clone)]
+pub struct Original {
+    /// Media type of the file, such as "image/png".
+    pub mime: String,
+    /// Contents of the file. Shared by every image in a multi-image file.
+    pub data: Arc<Vec<u8>>,
 }
 
 impl Icon {
     /// Download the image at href and use it to create an icon.
     fn download(client: &impl Downloader, href: &str) -> Result<Icon> {
-        let mut response = client.get(href)?;
+        Icon::decode(href, &Icon::fetch(client, href)?)
+    }
+
+    /// Download the image at href, creating an icon for each image it
+    /// contains.
+    fn download_all(client: &impl Downloader, href: &str) -> Result<Vec<Icon>> {
+        Icon::decode_all(href, &Icon::fetch(client, href)?)
+    }
+
+    /// Download the image data at href, rejecting error responses and
+    /// responses declared as something other than an image, such as the
+    /// html page some sites serve for missing files.
+    ///
+    /// `data:` URIs are decoded in place, without a request.
+    fn fetch(client: &impl Downloader, href: &str) -> Result<Vec<u8>> {
+        if data::is_data(href) {
+            let (media, icon_data) = data::decode(href)?;
+            Icon::check_type(href, &media)?;
+            return Ok(icon_data);
+        }
+        let mut response = client.fetch(Request::new(href))?.error_for_status()?;
+        if let Some(content_type) = response.content_type() {
+            Icon::check_type(href, &content_type)?;
+        }
         let mut icon_data: Vec<u8> = vec![];
-        copy(&mut response, &mut icon_data)?;
-        // FIXME: Fails on svg case, since image crate doesn't suppport svg.
-        // TODO: Handle svg.
-        let kind = image::guess_format(&icon_data)?;
-        let ext = match kind {
-            image::PNG => "png",
-            image::ICO => "ico",
-            image::JPEG => "jpeg",
-            _ => "",
+        copy(&mut response.body, &mut icon_data)?;
+        Ok(icon_data)
+    }
+
+    fn check_type(href: &str, content_type: &str) -> Result<()> {
+        if NOT_IMAGES.iter().any(|t| content_type.ends_with(t)) {
+            return Err(Error::Download(
+                format!("{} is {}, not an image", href, content_type).into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Score used to rank icons: the number of pixels, so that a large square
+    /// icon beats a wide but short banner.
+    pub fn score(&self) -> u64 {
+        u64::from(self.img.width()) * u64::from(self.img.height())
+    }
+
+    /// Load the image file at path and use it to create an icon.
+    pub fn open(path: &Path) -> Result<Icon> {
+        let mut icon = Icon::decode(&path.to_string_lossy(), &fs::read(path)?)?;
+        icon.name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into())
+            .unwrap_or_default();
+        Ok(icon)
+    }
+
+    /// Decode the image data loaded from source, choosing the largest image
+    /// if there are several.
+    fn decode(source: &str, data: &[u8]) -> Result<Icon> {
+        Ok(Icon::decode_all(source, data)?
+            .into_iter()
+            .max()
+            .expect("decoding yields at least one image"))
+    }
+
+    /// Decode every image in the data loaded from source. Icon files can
+    /// contain many images, each of which is a separate candidate.
+    fn decode_all(source: &str, data: &[u8]) -> Result<Vec<Icon>> {
+        let images = if decode::is_icns(data) {
+            vec![("icns", decode::icns(data)?)]
+        } else if decode::is_svg(data) {
+            vec![("svg", decode::svg(data)?)]
+        } else if decode::is_ico(data) {
+            decode::ico(data)?
+                .into_iter()
+                .map(|img| ("ico", img))
+                .collect()
+        } else if decode::is_webp(data) {
+            vec![("webp", decode::webp(data)?)]
+        } else if decode::is_avif(data) {
+            vec![("avif", decode::avif(data)?)]
+        } else {
+            // Animated gifs give their first frame.
+            let kind = image::guess_format(data)?;
+            let ext = match kind {
+                image::PNG => "png",
+                image::JPEG => "jpeg",
+                image::GIF => "gif",
+                image::BMP => "bmp",
+                _ => "",
+            };
+            vec![(
+                ext,
+                image::load_from_memory_with_format(data, kind)?.to_rgba(),
+            )]
         };
-        let img = image::load_from_memory(&icon_data)?;
-        Ok(Icon {
-            source: href.into(),
-            name: Url::parse(href)?.host_str().unwrap_or_else(|| "").into(),
-            img: img.to_rgba(),
-            ext: ext.into(),
-        })
+        let name: String = Url::parse(source)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .unwrap_or_default();
+        let data = Arc::new(data.to_vec());
+        Ok(images
+            .into_iter()
+            .map(|(ext, img)| Icon {
+                source: source.into(),
+                name: name.clone(),
+                original: mime(ext).map(|mime| Original {
+                    mime: mime.into(),
+                    data: data.clone(),
+                }),
+                img,
+                ext: ext.into(),
+            })
+            .collect())
+    }
+}
+
+/// Media type of images with the extension ext.
+fn mime(ext: &str) -> Option<&'static str> {
+    match ext {
+        "png" => Some("image/png"),
+        "jpeg" => Some("image/jpeg"),
+        "ico" => Some("image/x-icon"),
+        "svg" => Some("image/svg+xml"),
+        "icns" => Some("image/icns"),
+        "webp" => Some("image/webp"),
+        "avif" => Some("image/avif"),
+        "gif" => Some("image/gif"),
+        "bmp" => Some("image/bmp"),
+        _ => None,
     }
 }
 
@@ -215,11 +795,12 @@ impl PartialOrd for Icon {
     }
 }
 
+/// Icons are ranked by their score, then by their width.
 impl Ord for Icon {
     fn cmp(&self, other: &Icon) -> Ordering {
         let left: Size = self.img.dimensions().into();
         let right: Size = other.img.dimensions().into();
-        left.cmp(&right)
+        self.score().cmp(&other.score()).then(left.cmp(&right))
     }
 }
 
@@ -255,3 +836,373 @@ impl From<(u32, u32)> for Size {
         Size { w: d.0, h: d.1 }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// A 16x16 AVIF image of an opaque red square on a transparent
+    /// background.
+    #[cfg(feature = "avif")]
+    const AVIF: &str = concat!(
+        "AAAAGGZ0eXBhdmlmAAAAAG1pZjFtaWFmAAABaG1ldGEAAAAAAAAAIWhkbHIAAAAAAAAAAHBpY3QAAAAA",
+        "AAAAAAAAAAAAAAAADnBpdG0AAAAAAAEAAAAsaWxvYwAAAABEAAACAAIAAAABAAABiAAAAHgAAQAAAAEA",
+        "AAIAAAABCwAAADhpaW5mAAAAAAACAAAAFWluZmUCAAAAAAEAAGF2MDEAAAAAFWluZmUCAAAAAAIAAGF2",
+        "MDEAAAAAGmlyZWYAAAAAAAAADmF1eGwAAgABAAEAAACvaXBycAAAAIppcGNvAAAAFGlzcGUAAAAAAAAA",
+        "EAAAABAAAAAMYXYxQ4E/QAAAAAAQcGl4aQAAAAADCgoKAAAADGF2MUOBH1wAAAAADnBpeGkAAAAAAQoA",
+        "AAA4YXV4QwAAAAB1cm46bXBlZzptcGVnQjpjaWNwOnN5c3RlbXM6YXV4aWxpYXJ5OmFscGhhAAAAAB1p",
+        "cG1hAAAAAAAAAAIAAQMBggMAAgQBhAYFAAABi21kYXQSAAoFH8z/yNQybWQEwAAgABAAAAAAAAAAAAIh",
+        "Zp4/FwyGyIz3rWZhUVx77Wl2V9Fd/DxC5yEahddNmVSUkafxDbhRDwJd76OuDWw4SlGbieZrk8pnVqe/",
+        "bo7qQ1K6O4nd4p2Lo2OTniWeHbXmnbV4sy4I36K5X1ASAAoIP8z/yMBDQbQy/AFkBBgABAACAAAAAAAA",
+        "AAAAQAEMw5Xy625AgtWV0dKblQkGBd5h52bzC6IcuZpom0jZl2FrW3GbzBmqn8fqnBaPF8Oj4Oa1XOMb",
+        "wNnnxjbnl/HBdkkKK3eAbAWcsr1c/g5N7Vn09/f34h/nTxfQOrxG2XCcCkegbHgtPFgth2xDVQGald06",
+        "qlCE/ZosCabrvaTrWkpQEtGy1CzZxAFPV03To6tVoutFQg2T2AMyp5ayacuoZgibMwEXcUdx+mbGoV3S",
+        "Utb4RQF22Z6erbymthMNcbESFW0igbkwvkwqlvYiP0Oa4veA5qtjb1oF1Qta/6vxUwKmVuW8sWJk22Q=",
+    );
+
+    /// Serve connections that are accepted but never answered, returning the
+    /// url of an icon on the server.
+    fn stalled() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/favicon.ico", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut held = vec![];
+            for stream in listener.incoming() {
+                held.push(stream);
+            }
+        });
+        url
+    }
+
+    /// Refreshes serves pages whose markup only refreshes to target, given a
+    /// path such as "/a" to be appended to the page's url.
+    #[derive(Clone)]
+    struct Refreshes(&'static [(&'static str, &'static str)]);
+
+    impl Downloader for Refreshes {
+        fn fetch(&self, req: Request) -> Result<Response> {
+            let path = Url::parse(&req.url)?.path().to_string();
+            let target = self
+                .0
+                .iter()
+                .find(|(from, _)| *from == path)
+                .map(|(_, target)| *target)
+                .ok_or_else(|| Error::Download(format!("no page at {}", path).into()))?;
+            let markup = format!(r#"<meta http-equiv="refresh" content="0; url={}">"#, target);
+            Ok(Response {
+                url: req.url,
+                status: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: Box::new(std::io::Cursor::new(markup.into_bytes())),
+            })
+        }
+    }
+
+    #[test]
+    fn follow_refreshes() {
+        let inferer = Inferer::new(Refreshes(&[
+            ("/", "/landing"),
+            ("/landing", "/landing"),
+            ("/a", "/b"),
+            ("/b", "/a"),
+        ]));
+        let page = inferer.page("https://example.com/").unwrap();
+        assert_eq!(page.url.as_str(), "https://example.com/landing");
+        match inferer.page("https://example.com/a") {
+            Err(Error::Scrape(reason)) => assert!(reason.starts_with("more than 5 refreshes")),
+            result => panic!("expected a loop, got {:?}", result.map(|page| page.url)),
+        }
+    }
+
+    fn link(href: &str) -> Link {
+        Link {
+            href: href.into(),
+            rel: "icon".into(),
+            sizes: None,
+            color: None,
+        }
+    }
+
+    /// Link to an opaque square png of side size, as a data uri.
+    fn png(rel: &str, size: u32) -> Link {
+        let img = image::RgbaImage::from_pixel(size, size, Rgba([200, 30, 30, 255]));
+        Link {
+            href: format!(
+                "data:image/png;base64,{}",
+                base64::encode(&encode_png(&img))
+            ),
+            rel: rel.into(),
+            sizes: None,
+            color: None,
+        }
+    }
+
+    /// Encode img as a png file.
+    fn encode_png(img: &image::RgbaImage) -> Vec<u8> {
+        let mut data = vec![];
+        image::png::PNGEncoder::new(&mut data)
+            .encode(img, img.width(), img.height(), image::ColorType::RGBA(8))
+            .unwrap();
+        data
+    }
+
+    /// Encode img as the 32 bit bitmap of an ico file entry: a header
+    /// declaring twice the height, for the mask, then bottom up BGRA rows
+    /// and an empty mask.
+    fn encode_ico_bmp(img: &image::RgbaImage) -> Vec<u8> {
+        let (w, h) = img.dimensions();
+        let mask_row = (w.div_ceil(32) * 4) as usize;
+        let mut data = vec![];
+        for field in &[40, w, h * 2] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&[0; 24]);
+        for y in (0..h).rev() {
+            for x in 0..w {
+                let Rgba([r, g, b, a]) = *img.get_pixel(x, y);
+                data.extend_from_slice(&[b, g, r, a]);
+            }
+        }
+        data.extend(vec![0; mask_row * h as usize]);
+        data
+    }
+
+    /// Assemble an ico file from (side, frame, offset) entries. Frames follow
+    /// the directory, and entries point at their frame unless given an
+    /// explicit offset.
+    fn encode_ico(entries: &[(u32, &[u8], Option<u32>)]) -> Vec<u8> {
+        let mut data = vec![0, 0, 1, 0];
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        let mut offset = 6 + 16 * entries.len() as u32;
+        for (side, frame, explicit) in entries {
+            // Sides of 256 are written as 0.
+            data.extend_from_slice(&[*side as u8, *side as u8, 0, 0]);
+            data.extend_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&32u16.to_le_bytes());
+            data.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            data.extend_from_slice(&explicit.unwrap_or(offset).to_le_bytes());
+            offset += frame.len() as u32;
+        }
+        for (_, frame, _) in entries {
+            data.extend_from_slice(frame);
+        }
+        data
+    }
+
+    #[test]
+    fn ico_frames() {
+        let large = encode_png(&image::RgbaImage::from_pixel(
+            48,
+            48,
+            Rgba([0, 0, 255, 255]),
+        ));
+        let small = encode_ico_bmp(&image::RgbaImage::from_pixel(
+            16,
+            16,
+            Rgba([255, 0, 0, 128]),
+        ));
+        let ico = encode_ico(&[(16, &small, None), (48, &large, None)]);
+        let icons = Icon::decode_all("https://example.com/favicon.ico", &ico).unwrap();
+        let frames: Vec<_> = icons
+            .iter()
+            .map(|icon| {
+                (
+                    icon.ext.as_str(),
+                    icon.img.width(),
+                    *icon.img.get_pixel(0, 0),
+                )
+            })
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                ("ico", 16, Rgba([255, 0, 0, 128])),
+                ("ico", 48, Rgba([0, 0, 255, 255]))
+            ]
+        );
+        let inferer = Inferer::new(reqwest::Client::new());
+        let href = format!("data:image/x-icon;base64,{}", base64::encode(&ico));
+        assert_eq!(inferer.choose(vec![link(&href)]).unwrap().img.width(), 48);
+    }
+
+    #[test]
+    fn ico_bad_entries() {
+        let large = encode_png(&image::RgbaImage::new(48, 48));
+        // An entry pointing past the end of the file is skipped.
+        let ico = encode_ico(&[(48, &large, None), (32, &large, Some(1 << 20))]);
+        let icons = Icon::decode_all("favicon.ico", &ico).unwrap();
+        assert_eq!(icons.len(), 1);
+        // A file cut off within its directory is an error, not a panic.
+        let ico = encode_ico(&[(48, &large, None), (32, &large, None)]);
+        assert!(Icon::decode_all("favicon.ico", &ico[..6 + 16 + 8]).is_err());
+        // A file with nothing decodable is an error.
+        let ico = encode_ico(&[(16, b"not an image", None)]);
+        assert!(Icon::decode_all("favicon.ico", &ico).is_err());
+    }
+
+    #[test]
+    fn rejects_non_images() {
+        for (content_type, image) in &[
+            ("text/html", false),
+            ("application/json", false),
+            ("application/manifest+json", false),
+            ("application/javascript", false),
+            ("text/css", false),
+            ("image/png", true),
+            ("image/svg+xml", true),
+            ("image/x-icon", true),
+            ("application/octet-stream", true),
+            ("text/plain", true),
+            ("", true),
+        ] {
+            assert_eq!(
+                Icon::check_type("/favicon.ico", content_type).is_ok(),
+                *image,
+                "{}",
+                content_type
+            );
+        }
+        // Parameters such as the charset don't hide the type.
+        let client = reqwest::Client::new();
+        let err = Icon::fetch(&client, "data:text/html;charset=utf-8,<html></html>")
+            .unwrap_err()
+            .to_string();
+        assert!(err.ends_with("is text/html, not an image"), "{}", err);
+    }
+
+    #[test]
+    fn last_resorts() {
+        let inferer = Inferer::new(reqwest::Client::new());
+        let mask = Link {
+            color: Some(Rgba([0, 0, 0, 255])),
+            ..png("mask-icon", 512)
+        };
+        let tile = png("square310x310logo", 310);
+        let choose = |links: Vec<Link>| inferer.choose(links).unwrap().img.width();
+        assert_eq!(
+            choose(vec![mask.clone(), tile.clone(), png("icon", 32)]),
+            32
+        );
+        assert_eq!(
+            choose(vec![png("msapplication-TileImage", 144), png("icon", 16)]),
+            16
+        );
+        assert_eq!(choose(vec![mask.clone(), tile.clone()]), 512);
+        assert_eq!(choose(vec![link("data:image/png;base64,AAAA"), tile]), 310);
+        let mut candidates = inferer.download(vec![
+            link("data:image/png;base64,AAAA"),
+            mask,
+            png("apple-touch-icon", 180),
+        ]);
+        candidates.sort_by(|a, b| b.rank(a));
+        let rels: Vec<&str> = candidates.iter().map(|c| c.link.rel.as_str()).collect();
+        assert_eq!(rels, ["apple-touch-icon", "mask-icon", "icon"]);
+    }
+
+    #[test]
+    fn download_deadline() {
+        let mut inferer = Inferer::new(reqwest::Client::new());
+        inferer.deadline = Some(Duration::from_millis(300));
+        let started = Instant::now();
+        let candidates = inferer.download(vec![link(&stalled())]);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(candidates.len(), 1);
+        assert!(candidates[0].icon.is_none());
+        assert_eq!(
+            candidates[0].error.as_deref(),
+            Some("not downloaded before the deadline")
+        );
+    }
+
+    #[test]
+    fn download_cancel() {
+        let mut inferer = Inferer::new(reqwest::Client::new());
+        inferer.deadline = None;
+        let cancel = inferer.cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            cancel.store(true, AtomicOrdering::SeqCst);
+        });
+        let started = Instant::now();
+        let candidates = inferer.download(vec![link(&stalled()), link(&stalled())]);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(candidates.len(), 2);
+        for candidate in &candidates {
+            assert_eq!(candidate.error.as_deref(), Some("cancelled"));
+        }
+    }
+
+    #[test]
+    fn decodes_formats() {
+        use image::{Rgba, RgbaImage};
+        const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+        const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+        const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+        let mut corner = RgbaImage::from_pixel(4, 4, RED);
+        corner.put_pixel(0, 0, CLEAR);
+
+        // Lossless, so the transparent corner survives.
+        let mut webp = vec![];
+        image_webp::WebPEncoder::new(&mut webp)
+            .encode(&corner, 4, 4, image_webp::ColorType::Rgba8)
+            .unwrap();
+        let mut gif = vec![];
+        image::gif::Encoder::new(&mut gif)
+            .encode_frames(vec![
+                image::Frame::new(RgbaImage::from_pixel(4, 4, RED)),
+                image::Frame::new(RgbaImage::from_pixel(4, 4, BLUE)),
+            ])
+            .unwrap();
+        let mut bmp = vec![];
+        image::bmp::BMPEncoder::new(&mut bmp)
+            .encode(&[0, 0, 255].repeat(16), 4, 4, image::ColorType::RGB(8))
+            .unwrap();
+
+        for (ext, data, mime, expected) in &[
+            ("webp", webp, "image/webp", corner.clone()),
+            // Animated gifs give their first frame.
+            ("gif", gif, "image/gif", RgbaImage::from_pixel(4, 4, RED)),
+            ("bmp", bmp, "image/bmp", RgbaImage::from_pixel(4, 4, BLUE)),
+        ] {
+            let source = format!("https://example.com/icon.{}", ext);
+            let icons = Icon::decode_all(&source, data).unwrap();
+            assert_eq!(icons.len(), 1, "{}", ext);
+            let icon = &icons[0];
+            assert_eq!(icon.ext, *ext);
+            assert_eq!(icon.original.as_ref().unwrap().mime, *mime);
+            assert_eq!(icon.img.dimensions(), expected.dimensions(), "{}", ext);
+            assert_eq!(icon.img.as_ref(), expected.as_ref() as &[u8], "{}", ext);
+        }
+    }
+
+    #[cfg(not(feature = "avif"))]
+    #[test]
+    fn avif_needs_feature() {
+        let data = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+        let err = Icon::decode_all("https://example.com/icon.avif", data).unwrap_err();
+        assert!(err.to_string().contains("\"avif\" feature"), "{}", err);
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn decodes_avif() {
+        let data = base64::decode(AVIF).unwrap();
+        let icons = Icon::decode_all("https://example.com/icon.avif", &data).unwrap();
+        assert_eq!(icons.len(), 1);
+        let icon = &icons[0];
+        assert_eq!(icon.ext, "avif");
+        assert_eq!(icon.original.as_ref().unwrap().mime, "image/avif");
+        assert_eq!(icon.img.dimensions(), (16, 16));
+        // Lossy, so only roughly the colors encoded.
+        let center = icon.img.get_pixel(8, 8);
+        assert!(
+            center[0] > 200 && center[1] < 50 && center[3] > 200,
+            "{:?}",
+            center
+        );
+        assert!(icon.img.get_pixel(0, 0)[3] < 50);
+    }
+}
@@ -0,0 +1,433 @@
+//! Credentials for sites that sit behind a login, such as internal apps behind
+//! single sign-on, so that inference sees the app rather than the login page.
+use super::{Downloader, Request, Response, Result};
+use crate::error::Error;
+use base64;
+use log::debug;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Auth is a Downloader that sends credentials along with the requests made
+/// through inner.
+///
+/// Headers are sent to the site's host and its subdomains, so that icons
+/// served by a CDN don't see them. Cookies are sent wherever their domain and
+/// path match, as a browser would.
+#[derive(Debug, Clone)]
+pub struct Auth<D: Downloader> {
+    /// Downloader the credentials are added to.
+    pub inner: D,
+    /// Host of the site, such as "app.example.com".
+    pub host: String,
+    /// Headers to send to the site, such as an Authorization header.
+    pub headers: HeaderMap,
+    pub cookies: Vec<Cookie>,
+}
+
+impl<D: Downloader> Auth<D> {
+    /// Send no credentials yet to the site with host.
+    pub fn new(host: &str, inner: D) -> Auth<D> {
+        Auth {
+            inner,
+            host: host.to_lowercase(),
+            headers: HeaderMap::new(),
+            cookies: vec![],
+        }
+    }
+
+    /// Log into the site with a username and password, using HTTP basic
+    /// authentication.
+    pub fn basic(&mut self, user: &str, password: &str) -> Result<()> {
+        let token = base64::encode(&format!("{}:{}", user, password));
+        let value = HeaderValue::from_str(&format!("Basic {}", token))
+            .map_err(|err| Error::Download(format!("invalid basic auth: {}", err).into()))?;
+        self.headers.insert(AUTHORIZATION, value);
+        Ok(())
+    }
+
+    /// Send a cookie to the site and its subdomains.
+    pub fn cookie(&mut self, name: &str, value: &str) {
+        self.cookies.push(Cookie {
+            domain: self.host.clone(),
+            subdomains: true,
+            path: "/".into(),
+            secure: false,
+            expires: None,
+            name: name.into(),
+            value: value.into(),
+        });
+    }
+}
+
+impl<D: Downloader> Downloader for Auth<D> {
+    fn fetch(&self, mut req: Request) -> Result<Response> {
+        let url = Url::parse(&req.url)?;
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        if domain_match(&host, &self.host, true) {
+            for name in self.headers.keys() {
+                req.headers.remove(name);
+            }
+            for (name, value) in &self.headers {
+                req.headers.append(name, value.clone());
+            }
+            req.credentials |= !self.headers.is_empty();
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let cookies: Vec<String> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(&url, &host, now))
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        if !cookies.is_empty() {
+            match HeaderValue::from_str(&cookies.join("; ")) {
+                Ok(value) => {
+                    req.headers.insert(COOKIE, value);
+                    req.credentials = true;
+                }
+                Err(err) => debug!("sending cookies to {}: {}", &req.url, err),
+            }
+        }
+        self.inner.fetch(req)
+    }
+}
+
+/// Cookie is a cookie sent to the sites it matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    /// Domain the cookie is sent to, such as "example.com".
+    pub domain: String,
+    /// Whether the cookie is also sent to subdomains of domain.
+    pub subdomains: bool,
+    /// Path the cookie is limited to, such as "/" for the whole site.
+    pub path: String,
+    /// Whether the cookie is only sent over https.
+    pub secure: bool,
+    /// Seconds since the unix epoch after which the cookie is no longer sent,
+    /// or None for session cookies.
+    pub expires: Option<u64>,
+    pub name: String,
+    pub value: String,
+}
+
+impl Cookie {
+    /// Whether the cookie should be sent with a request for url, whose
+    /// lowercase host is given, at now seconds since the unix epoch.
+    fn matches(&self, url: &Url, host: &str, now: u64) -> bool {
+        domain_match(host, &self.domain, self.subdomains)
+            && path_match(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && self.expires.map_or(true, |expires| expires > now)
+    }
+}
+
+/// Parse the cookies in a Netscape cookies.txt file, as exported by browser
+/// extensions and written by curl and wget.
+///
+/// Each line holds the tab separated domain, subdomain flag, path, secure
+/// flag, expiry, name and value of a cookie. Comments, blank lines and
+/// malformed lines are skipped.
+pub fn cookies_txt(text: &str) -> Vec<Cookie> {
+    text.lines()
+        .filter_map(|line| {
+            // curl marks HttpOnly cookies with a prefix that looks like a
+            // comment.
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.trim().is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 7 {
+                debug!("skipping malformed cookies.txt line: {:?}", line);
+                return None;
+            }
+            let expires = match fields[4].trim().parse::<u64>() {
+                Ok(0) => None,
+                Ok(expires) => Some(expires),
+                Err(_) => {
+                    debug!("skipping cookie with invalid expiry: {:?}", line);
+                    return None;
+                }
+            };
+            let domain = fields[0].trim().to_lowercase();
+            Some(Cookie {
+                subdomains: fields[1].eq_ignore_ascii_case("TRUE") || domain.starts_with('.'),
+                domain: domain.trim_start_matches('.').into(),
+                path: fields[2].into(),
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                expires,
+                name: fields[5].into(),
+                value: fields[6].trim_end_matches('\r').into(),
+            })
+        })
+        .collect()
+}
+
+/// Parse a header written as "Name: value".
+pub fn header(text: &str) -> Result<(HeaderName, HeaderValue)> {
+    let invalid = |reason: &str| Error::Download(format!("header {:?}: {}", text, reason).into());
+    let mut parts = text.splitn(2, ':');
+    let name = parts.next().unwrap_or_default().trim();
+    let value = parts
+        .next()
+        .ok_or_else(|| invalid("expected \"Name: value\""))?;
+    Ok((
+        HeaderName::from_bytes(name.as_bytes()).map_err(|err| invalid(&err.to_string()))?,
+        HeaderValue::from_str(value.trim()).map_err(|err| invalid(&err.to_string()))?,
+    ))
+}
+
+/// Whether host is domain, or a subdomain of it when subdomains is set.
+fn domain_match(host: &str, domain: &str, subdomains: bool) -> bool {
+    host == domain
+        || (subdomains
+            && host.len() > domain.len()
+            && host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.'))
+}
+
+/// Whether a request for path falls under the path of a cookie, as in
+/// https://tools.ietf.org/html/rfc6265#section-5.1.4.
+fn path_match(path: &str, cookie: &str) -> bool {
+    path == cookie
+        || (path.starts_with(cookie)
+            && (cookie.ends_with('/') || path[cookie.len()..].starts_with('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// Origin records the requests it gets, answering each with no content.
+    #[derive(Clone, Default)]
+    struct Origin(Arc<Mutex<Vec<Request>>>);
+
+    impl Downloader for Origin {
+        fn fetch(&self, req: Request) -> Result<Response> {
+            let url = req.url.clone();
+            self.0.lock().unwrap().push(req);
+            Ok(Response {
+                url,
+                status: StatusCode::NO_CONTENT,
+                headers: HeaderMap::new(),
+                body: Box::new(Cursor::new(vec![])),
+            })
+        }
+    }
+
+    fn cookie(domain: &str, subdomains: bool, path: &str) -> Cookie {
+        Cookie {
+            domain: domain.into(),
+            subdomains,
+            path: path.into(),
+            secure: false,
+            expires: None,
+            name: "session".into(),
+            value: "secret".into(),
+        }
+    }
+
+    #[test]
+    fn domains() {
+        for (host, domain, subdomains, expected) in &[
+            ("example.com", "example.com", false, true),
+            ("example.com", "example.com", true, true),
+            ("app.example.com", "example.com", true, true),
+            ("a.b.example.com", "example.com", true, true),
+            ("app.example.com", "example.com", false, false),
+            ("badexample.com", "example.com", true, false),
+            ("example.com.evil.com", "example.com", true, false),
+            ("example.com", "app.example.com", true, false),
+            ("com", "example.com", true, false),
+        ] {
+            assert_eq!(
+                domain_match(host, domain, *subdomains),
+                *expected,
+                "{} {} {}",
+                host,
+                domain,
+                subdomains
+            );
+        }
+    }
+
+    #[test]
+    fn paths() {
+        for (path, cookie, expected) in &[
+            ("/", "/", true),
+            ("/app", "/", true),
+            ("/app", "/app", true),
+            ("/app/", "/app", true),
+            ("/app/icon.png", "/app", true),
+            ("/app/icon.png", "/app/", true),
+            ("/application", "/app", false),
+            ("/", "/app", false),
+            ("/other/app", "/app", false),
+        ] {
+            assert_eq!(path_match(path, cookie), *expected, "{} {}", path, cookie);
+        }
+    }
+
+    #[test]
+    fn cookie_matches() {
+        let now = 1_600_000_000;
+        let secure = Cookie {
+            secure: true,
+            ..cookie("example.com", true, "/")
+        };
+        let expiring = |expires| Cookie {
+            expires: Some(expires),
+            ..cookie("example.com", true, "/")
+        };
+        for (cookie, url, expected) in &[
+            (
+                cookie("example.com", true, "/"),
+                "http://app.example.com/",
+                true,
+            ),
+            (
+                cookie("example.com", false, "/"),
+                "http://app.example.com/",
+                false,
+            ),
+            (
+                cookie("example.com", true, "/app"),
+                "http://example.com/app/icon.png",
+                true,
+            ),
+            (
+                cookie("example.com", true, "/app"),
+                "http://example.com/favicon.ico",
+                false,
+            ),
+            (secure.clone(), "https://example.com/", true),
+            (secure, "http://example.com/", false),
+            (expiring(now + 1), "http://example.com/", true),
+            (expiring(now), "http://example.com/", false),
+            (expiring(now - 1), "http://example.com/", false),
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(
+                cookie.matches(&url, url.host_str().unwrap(), now),
+                *expected,
+                "{:?} {}",
+                cookie,
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn parses_cookies_txt() {
+        let text = [
+            "# Netscape HTTP Cookie File",
+            "",
+            ".example.com\tTRUE\t/\tFALSE\t0\tsession\tsecret",
+            "#HttpOnly_app.example.com\tFALSE\t/app\tTRUE\t1600000000\ttoken\tabc=def\r",
+            "example.com\tFALSE\t/\tFALSE\t0\ttoo\tmany\tfields",
+            "example.com\tFALSE\t/\tFALSE\t0\ttoo-few",
+            "example.com\tFALSE\t/\tFALSE\tnever\texpiry\tinvalid",
+            "# example.com\tFALSE\t/\tFALSE\t0\tcommented\tout",
+        ]
+        .join("\n");
+        assert_eq!(
+            cookies_txt(&text),
+            vec![
+                Cookie {
+                    domain: "example.com".into(),
+                    subdomains: true,
+                    path: "/".into(),
+                    secure: false,
+                    expires: None,
+                    name: "session".into(),
+                    value: "secret".into(),
+                },
+                Cookie {
+                    domain: "app.example.com".into(),
+                    subdomains: false,
+                    path: "/app".into(),
+                    secure: true,
+                    expires: Some(1_600_000_000),
+                    name: "token".into(),
+                    value: "abc=def".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn headers() {
+        for (text, expected) in &[
+            (
+                "Authorization: Bearer abc",
+                Some(("authorization", "Bearer abc")),
+            ),
+            ("X-Api-Key:abc", Some(("x-api-key", "abc"))),
+            ("  X-Api-Key :  a: b  ", Some(("x-api-key", "a: b"))),
+            ("X-Empty:", Some(("x-empty", ""))),
+            ("Authorization", None),
+            (": abc", None),
+            ("Bad Name: abc", None),
+            ("X-Api-Key: a\nb", None),
+        ] {
+            let parsed = header(text).ok();
+            let parsed = parsed
+                .as_ref()
+                .map(|(name, value)| (name.as_str(), value.to_str().unwrap()));
+            assert_eq!(parsed, *expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn scopes_credentials() {
+        let origin = Origin::default();
+        let mut auth = Auth::new("App.Example.com", origin.clone());
+        auth.basic("user", "password").unwrap();
+        auth.cookie("session", "secret");
+        auth.cookies.push(cookie("cdn.com", true, "/"));
+        for url in &[
+            "https://app.example.com/",
+            "https://static.app.example.com/icon.png",
+            "https://example.com/",
+            "https://cdn.com/icon.png",
+            "https://other.com/icon.png",
+        ] {
+            let mut req = Request::new(url);
+            req.headers
+                .insert(AUTHORIZATION, HeaderValue::from_static("replaced"));
+            auth.fetch(req).unwrap();
+        }
+        let requests = origin.0.lock().unwrap();
+        let sent: Vec<_> = requests
+            .iter()
+            .map(|req| {
+                (
+                    req.headers
+                        .get_all(AUTHORIZATION)
+                        .iter()
+                        .map(|v| v.to_str().unwrap())
+                        .collect::<Vec<_>>(),
+                    req.headers.get(COOKIE).map(|v| v.to_str().unwrap()),
+                    req.credentials,
+                )
+            })
+            .collect();
+        let basic = "Basic dXNlcjpwYXNzd29yZA==";
+        assert_eq!(
+            sent,
+            vec![
+                (vec![basic], Some("session=secret"), true),
+                (vec![basic], Some("session=secret"), true),
+                (vec!["replaced"], None, false),
+                (vec!["replaced"], Some("session=secret"), true),
+                (vec!["replaced"], None, false),
+            ]
+        );
+    }
+}
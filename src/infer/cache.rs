@@ -0,0 +1,355 @@
+use super::{Downloader, Request, Response, Result};
+use dirs;
+use log::{debug, warn};
+use reqwest::header::{
+    HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, COOKIE, ETAG, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::env;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+/// Directory nativefier caches downloads in.
+/// Falls back to the temporary directory when the OS doesn't provide a cache
+/// directory.
+pub fn dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join("nativefier")
+}
+
+/// Remove every cached download stored in dir.
+pub fn clear(dir: &Path) -> Result<()> {
+    let http = dir.join("http");
+    if http.exists() {
+        fs::remove_dir_all(&http)?;
+    }
+    Ok(())
+}
+
+/// Cache is a Downloader that keeps response bodies on disk, keyed by url.
+///
+/// Cached responses are revalidated with the origin using their ETag and
+/// Last-Modified validators, so a page or icon is only downloaded again when
+/// it has changed. If the origin can't be reached the cached body is served
+/// as is.
+///
+/// Requests that carry credentials, such as cookies, bypass the cache, so that
+/// authenticated pages are neither stored unencrypted nor served to runs
+/// without the credentials.
+#[derive(Debug, Clone)]
+pub struct Cache<D: Downloader> {
+    /// Directory to store cached responses in.
+    pub dir: PathBuf,
+    /// Downloader used to reach the origin.
+    pub inner: D,
+}
+
+impl<D: Downloader> Cache<D> {
+    /// Cache responses from inner in the default cache directory.
+    pub fn new(inner: D) -> Cache<D> {
+        Cache { dir: dir(), inner }
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir.join("http").join(key(url))
+    }
+
+    fn load(&self, url: &str) -> Option<(Entry, Vec<u8>)> {
+        let path = self.path(url);
+        let entry: Entry = match fs::read(path.with_extension("json"))
+            .map_err(|err| err.to_string())
+            .and_then(|buf| serde_json::from_slice(&buf).map_err(|err| err.to_string()))
+        {
+            Ok(entry) => entry,
+            Err(err) => {
+                debug!("loading cache entry for {}: {}", url, err);
+                return None;
+            }
+        };
+        match fs::read(path.with_extension("body")) {
+            Ok(body) => Some((entry, body)),
+            Err(err) => {
+                debug!("loading cached body for {}: {}", url, err);
+                None
+            }
+        }
+    }
+
+    fn store(&self, url: &str, entry: &Entry, body: &[u8]) {
+        let path = self.path(url);
+        let result = fs::create_dir_all(self.dir.join("http"))
+            .and_then(|_| fs::write(path.with_extension("body"), body))
+            .and_then(|_| {
+                serde_json::to_vec(entry)
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+            })
+            .and_then(|buf| fs::write(path.with_extension("json"), buf));
+        if let Err(err) = result {
+            debug!("storing cache entry for {}: {}", url, err);
+        }
+    }
+}
+
+impl<D: Downloader> Downloader for Cache<D> {
+    fn fetch(&self, req: Request) -> Result<Response> {
+        if req.credentials
+            || req.headers.contains_key(AUTHORIZATION)
+            || req.headers.contains_key(COOKIE)
+        {
+            debug!("not caching authenticated request for {}", &req.url);
+            return self.inner.fetch(req);
+        }
+        let url = req.url.clone();
+        let cached = self.load(&url);
+        let mut conditional = req;
+        if let Some((entry, _)) = &cached {
+            if let Some(etag) = entry.etag.as_ref().and_then(|v| v.parse().ok()) {
+                conditional.headers.insert(IF_NONE_MATCH, etag);
+            }
+            if let Some(date) = entry.last_modified.as_ref().and_then(|v| v.parse().ok()) {
+                conditional.headers.insert(IF_MODIFIED_SINCE, date);
+            }
+        }
+        let mut response = match (self.inner.fetch(conditional), cached) {
+            (Ok(response), Some((entry, body))) if response.status == StatusCode::NOT_MODIFIED => {
+                debug!("cache hit: {}", &url);
+                return Ok(entry.into_response(body));
+            }
+            (Ok(response), _) => response,
+            (Err(err), Some((entry, body))) => {
+                warn!("serving {} from cache: {}", &url, err);
+                return Ok(entry.into_response(body));
+            }
+            (Err(err), None) => return Err(err),
+        };
+        let entry = Entry::from(&response);
+        if !response.status.is_success() || (entry.etag.is_none() && entry.last_modified.is_none())
+        {
+            return Ok(response);
+        }
+        let mut body = vec![];
+        response.body.read_to_end(&mut body)?;
+        self.store(&url, &entry, &body);
+        response.body = Box::new(Cursor::new(body));
+        Ok(response)
+    }
+}
+
+/// Entry is the metadata stored alongside a cached body.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// Final url of the response, after any redirects.
+    url: String,
+    status: u16,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+}
+
+impl Entry {
+    fn into_response(self, body: Vec<u8>) -> Response {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            (CONTENT_TYPE, self.content_type),
+            (ETAG, self.etag),
+            (LAST_MODIFIED, self.last_modified),
+        ]
+        .iter()
+        .cloned()
+        {
+            if let Some(value) = value.and_then(|v| HeaderValue::from_str(&v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        Response {
+            url: self.url,
+            status: StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK),
+            headers,
+            body: Box::new(Cursor::new(body)),
+        }
+    }
+}
+
+impl From<&Response> for Entry {
+    fn from(response: &Response) -> Entry {
+        let header = |name| {
+            response
+                .headers
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(String::from)
+        };
+        Entry {
+            url: response.url.clone(),
+            status: response.status.as_u16(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            content_type: header(CONTENT_TYPE),
+        }
+    }
+}
+
+/// Derive a filename for url using 64 bit FNV-1a, which, unlike the std
+/// hasher, is stable across builds.
+fn key(url: &str) -> String {
+    let hash = url.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::infer::auth::Auth;
+    use crate::infer::tempdir::TempDir;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// Status, ETag and body of a scripted response, or None to fail.
+    type Scripted = Option<(u16, Option<&'static str>, &'static str)>;
+
+    /// Origin serves scripted responses, recording the requests it gets.
+    #[derive(Clone, Default)]
+    struct Origin {
+        responses: Arc<Mutex<VecDeque<Scripted>>>,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl Origin {
+        /// Serve response next.
+        fn then(&self, response: Scripted) {
+            self.responses.lock().unwrap().push_back(response);
+        }
+    }
+
+    impl Downloader for Origin {
+        fn fetch(&self, req: Request) -> Result<Response> {
+            let url = req.url.clone();
+            self.requests.lock().unwrap().push(req);
+            let (status, etag, body) = match self.responses.lock().unwrap().pop_front().unwrap() {
+                Some(response) => response,
+                None => return Err(Error::Download("unreachable".into())),
+            };
+            let mut headers = HeaderMap::new();
+            if let Some(etag) = etag {
+                headers.insert(ETAG, HeaderValue::from_static(etag));
+            }
+            Ok(Response {
+                url,
+                status: StatusCode::from_u16(status).unwrap(),
+                headers,
+                body: Box::new(Cursor::new(body.as_bytes().to_vec())),
+            })
+        }
+    }
+
+    fn cache(dir: &Path) -> (Cache<Origin>, Origin) {
+        let origin = Origin::default();
+        (
+            Cache {
+                dir: dir.into(),
+                inner: origin.clone(),
+            },
+            origin,
+        )
+    }
+
+    fn get(cache: &Cache<Origin>, req: Request) -> Result<String> {
+        let mut body = String::new();
+        cache.fetch(req)?.body.read_to_string(&mut body)?;
+        Ok(body)
+    }
+
+    const URL: &str = "https://example.com/favicon.ico";
+
+    #[test]
+    fn revalidates() {
+        let dir = TempDir::new("revalidates");
+        let (cache, origin) = cache(&dir);
+        origin.then(Some((200, Some("\"v1\""), "icon")));
+        assert_eq!(get(&cache, Request::new(URL)).unwrap(), "icon");
+        origin.then(Some((304, None, "")));
+        assert_eq!(get(&cache, Request::new(URL)).unwrap(), "icon");
+        let requests = origin.requests.lock().unwrap();
+        assert_eq!(requests[0].headers.get(IF_NONE_MATCH), None);
+        assert_eq!(requests[1].headers[IF_NONE_MATCH], "\"v1\"");
+    }
+
+    #[test]
+    fn serves_stale_when_unreachable() {
+        let dir = TempDir::new("stale");
+        let (cache, origin) = cache(&dir);
+        origin.then(Some((200, Some("\"v1\""), "icon")));
+        get(&cache, Request::new(URL)).unwrap();
+        origin.then(None);
+        assert_eq!(get(&cache, Request::new(URL)).unwrap(), "icon");
+    }
+
+    #[test]
+    fn skips_responses_without_validators() {
+        let dir = TempDir::new("validators");
+        let (cache, origin) = cache(&dir);
+        origin.then(Some((200, None, "icon")));
+        get(&cache, Request::new(URL)).unwrap();
+        origin.then(None);
+        assert!(get(&cache, Request::new(URL)).is_err());
+        assert!(!cache.path(URL).with_extension("body").exists());
+    }
+
+    #[test]
+    fn bypasses_authenticated_requests() {
+        let dir = TempDir::new("authenticated");
+        let (cache, origin) = cache(&dir);
+        origin.then(Some((200, Some("\"v1\""), "public")));
+        get(&cache, Request::new(URL)).unwrap();
+        let mut req = Request::new(URL);
+        req.headers
+            .insert(COOKIE, HeaderValue::from_static("session=secret"));
+        origin.then(Some((200, Some("\"v2\""), "private")));
+        assert_eq!(get(&cache, req).unwrap(), "private");
+        // Neither stored nor revalidated against the public copy.
+        assert_eq!(
+            origin.requests.lock().unwrap()[1]
+                .headers
+                .get(IF_NONE_MATCH),
+            None
+        );
+        origin.then(None);
+        assert_eq!(get(&cache, Request::new(URL)).unwrap(), "public");
+    }
+
+    #[test]
+    fn bypasses_auth() {
+        let dir = TempDir::new("auth");
+        let (cache, origin) = cache(&dir);
+        origin.then(Some((200, Some("\"v1\""), "public")));
+        get(&cache, Request::new(URL)).unwrap();
+        // Any header Auth adds is a credential, not only Authorization.
+        let mut auth = Auth::new("example.com", cache.clone());
+        auth.headers
+            .insert("x-api-key", HeaderValue::from_static("secret"));
+        origin.then(Some((200, Some("\"v2\""), "private")));
+        let mut body = String::new();
+        auth.fetch(Request::new(URL))
+            .unwrap()
+            .body
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "private");
+        assert_eq!(
+            origin.requests.lock().unwrap()[1]
+                .headers
+                .get(IF_NONE_MATCH),
+            None
+        );
+        origin.then(None);
+        assert_eq!(get(&cache, Request::new(URL)).unwrap(), "public");
+    }
+}
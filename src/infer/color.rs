@@ -0,0 +1,254 @@
+//! Colors as written in markup, and colors derived from a site.
+use super::manifest::Manifest;
+use image::{Rgba, RgbaImage};
+use scraper::{Html, Selector};
+use std::collections::HashMap;
+
+/// Colors are the accent colors of a site.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Colors {
+    /// Accent color, as used for title bars and tiles.
+    pub theme: Option<Rgba<u8>>,
+    /// Color shown behind the site while it loads.
+    pub background: Option<Rgba<u8>>,
+}
+
+impl Colors {
+    /// Fill in a theme the site doesn't declare with the dominant color of
+    /// its icon.
+    pub fn or_dominant(mut self, icon: &RgbaImage) -> Colors {
+        if self.theme.is_none() {
+            self.theme = dominant(icon);
+            self.background = self.background.or(self.theme);
+        }
+        self
+    }
+}
+
+/// Collect the colors a page declares in its markup and manifest.
+///
+/// The theme is taken from `<meta name="theme-color">`, preferring tags
+/// without a media query or for light mode, then the manifest's theme_color,
+/// then `<meta name="msapplication-TileColor">`. Tags for dark mode are
+/// skipped, since apps open in light mode. The background is the manifest's
+/// background_color, falling back to the theme.
+pub fn from_page(doc: &Html, manifest: Option<&Manifest>) -> Colors {
+    let theme_el = Selector::parse(r#"meta[name="theme-color"]"#).unwrap();
+    let mut light = None;
+    for el in doc.select(&theme_el) {
+        let color = match el.value().attr("content").and_then(parse) {
+            Some(color) => color,
+            None => continue,
+        };
+        let media: String = el
+            .value()
+            .attr("media")
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_lowercase();
+        if !media.contains("prefers-color-scheme:dark") {
+            light = light.or(Some(color));
+        }
+    }
+    let tile_el = Selector::parse(r#"meta[name="msapplication-TileColor"]"#).unwrap();
+    let tile = doc
+        .select(&tile_el)
+        .filter_map(|el| el.value().attr("content"))
+        .filter_map(parse)
+        .next();
+    let declared = |color: fn(&Manifest) -> &Option<String>| {
+        manifest
+            .and_then(|m| color(m).as_ref())
+            .and_then(|c| parse(c))
+    };
+    let theme = light.or_else(|| declared(|m| &m.theme_color)).or(tile);
+    Colors {
+        theme,
+        background: declared(|m| &m.background_color).or(theme),
+    }
+}
+
+/// Find the dominant color of an image: the most common color among its
+/// opaque pixels, ignoring slight differences in shade.
+///
+/// Greys, such as a white background or black outline, only win when no
+/// colorful part covers at least a tenth of the image.
+pub fn dominant(img: &RgbaImage) -> Option<Rgba<u8>> {
+    // Pixel count and channel sums for each bucket of similar colors.
+    let mut buckets: HashMap<[u8; 3], (u64, [u64; 3])> = HashMap::new();
+    let mut opaque = 0;
+    for &Rgba([r, g, b, a]) in img.pixels() {
+        if a < 128 {
+            continue;
+        }
+        opaque += 1;
+        let bucket = buckets.entry([r >> 4, g >> 4, b >> 4]).or_default();
+        bucket.0 += 1;
+        for (sum, v) in bucket.1.iter_mut().zip(&[r, g, b]) {
+            *sum += u64::from(*v);
+        }
+    }
+    let is_colorful = |key: &[u8; 3]| {
+        let max = key.iter().max().unwrap_or(&0);
+        let min = key.iter().min().unwrap_or(&0);
+        max - min >= 3
+    };
+    let colorful = buckets
+        .iter()
+        .filter(|(key, _)| is_colorful(key))
+        .max_by_key(|(_, (count, _))| *count)
+        .filter(|(_, (count, _))| count * 10 >= opaque);
+    let (_, (count, sums)) =
+        colorful.or_else(|| buckets.iter().max_by_key(|(_, (count, _))| *count))?;
+    let average = |sum: u64| (sum / count) as u8;
+    Some(Rgba([
+        average(sums[0]),
+        average(sums[1]),
+        average(sums[2]),
+        255,
+    ]))
+}
+
+/// Parse a css color: "#rgb", "#rrggbb", "#rrggbbaa", "rgb(r, g, b)" or
+/// "rgba(r, g, b, a)". Named colors are only understood for the few basics.
+pub fn parse(s: &str) -> Option<Rgba<u8>> {
+    let s = s.trim().to_lowercase();
+    if let Some(hex) = s.strip_prefix('#') {
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()?;
+        return match digits.len() {
+            3 => Some(Rgba([digits[0] * 17, digits[1] * 17, digits[2] * 17, 255])),
+            6 | 8 => {
+                let byte = |ii: usize| digits[ii] * 16 + digits[ii + 1];
+                let alpha = if digits.len() == 8 { byte(6) } else { 255 };
+                Some(Rgba([byte(0), byte(2), byte(4), alpha]))
+            }
+            _ => None,
+        };
+    }
+    if let Some(args) = s
+        .strip_prefix("rgba(")
+        .or_else(|| s.strip_prefix("rgb("))
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let parts: Vec<&str> = args.split(',').map(str::trim).collect();
+        if parts.len() < 3 {
+            return None;
+        }
+        let channel = |part: &str| part.parse::<f32>().ok().map(|v| v.clamp(0.0, 255.0) as u8);
+        let alpha = match parts.get(3) {
+            Some(a) => (a.parse::<f32>().ok()?.clamp(0.0, 1.0) * 255.0) as u8,
+            None => 255,
+        };
+        return Some(Rgba([
+            channel(parts[0])?,
+            channel(parts[1])?,
+            channel(parts[2])?,
+            alpha,
+        ]));
+    }
+    match s.as_str() {
+        "black" => Some(Rgba([0, 0, 0, 255])),
+        "white" => Some(Rgba([255, 255, 255, 255])),
+        "red" => Some(Rgba([255, 0, 0, 255])),
+        "green" => Some(Rgba([0, 128, 0, 255])),
+        "blue" => Some(Rgba([0, 0, 255, 255])),
+        "gray" | "grey" => Some(Rgba([128, 128, 128, 255])),
+        _ => None,
+    }
+}
+
+/// Tint a monochrome image, such as a mask icon, with color, keeping only the
+/// shape given by its alpha channel.
+pub fn tint(img: &mut RgbaImage, color: Rgba<u8>) {
+    let Rgba([r, g, b, a]) = color;
+    for pixel in img.pixels_mut() {
+        let alpha = (u16::from(pixel[3]) * u16::from(a) / 255) as u8;
+        *pixel = Rgba([r, g, b, alpha]);
+    }
+}
+
+/// Derive a stable, reasonably saturated color from text, such as a host
+/// name, so that the same site always gets the same color.
+pub fn from_text(text: &str) -> Rgba<u8> {
+    let hash = text.bytes().fold(0x811c_9dc5_u32, |hash, b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+    hsl((hash % 360) as f32, 0.55, 0.45)
+}
+
+/// Format color as css does, ignoring alpha: "#rrggbb".
+pub fn hex(color: Rgba<u8>) -> String {
+    let [r, g, b, _] = color.0;
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Relative luminance of a color, from 0 (black) to 1 (white).
+pub fn luminance(color: Rgba<u8>) -> f32 {
+    let [r, g, b, _] = color.0;
+    (0.2126 * f32::from(r) + 0.7152 * f32::from(g) + 0.0722 * f32::from(b)) / 255.0
+}
+
+/// Convert hue (degrees), saturation and lightness into an opaque color.
+fn hsl(h: f32, s: f32, l: f32) -> Rgba<u8> {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+    let (r, g, b) = match h as u32 / 60 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let channel = |v: f32| ((v + m) * 255.0).round() as u8;
+    Rgba([channel(r), channel(g), channel(b), 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declared() {
+        let doc = Html::parse_document(
+            r##"<meta name="theme-color" media="(prefers-color-scheme: dark)" content="#000011">
+            <meta name="theme-color" media="(prefers-color-scheme: light)" content="#f00">
+            <meta name="msapplication-TileColor" content="#00ff00">"##,
+        );
+        let colors = from_page(&doc, None);
+        assert_eq!(colors.theme, Some(Rgba([255, 0, 0, 255])));
+        assert_eq!(colors.background, colors.theme);
+        let doc = Html::parse_document(
+            r##"<meta name="theme-color" media="(prefers-color-scheme: dark)" content="#000011">"##,
+        );
+        assert_eq!(from_page(&doc, None), Colors::default());
+    }
+
+    #[test]
+    fn or_dominant() {
+        let icon = RgbaImage::from_pixel(8, 8, Rgba([200, 30, 30, 255]));
+        let colors = Colors::default().or_dominant(&icon);
+        assert_eq!(colors.theme, Some(Rgba([200, 30, 30, 255])));
+        assert_eq!(colors.background, colors.theme);
+        let declared = Colors {
+            theme: Some(Rgba([0, 0, 255, 255])),
+            background: None,
+        };
+        assert_eq!(declared.or_dominant(&icon), declared);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(hex(Rgba([255, 8, 128, 10])), "#ff0880");
+        assert_eq!(
+            parse(&hex(Rgba([1, 2, 3, 255]))),
+            Some(Rgba([1, 2, 3, 255]))
+        );
+    }
+}
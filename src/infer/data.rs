@@ -0,0 +1,78 @@
+//! Inline `data:` URIs, which some sites use for icons so that they load
+//! without another request. See https://tools.ietf.org/html/rfc2397.
+use super::Result;
+use crate::error::Error;
+use base64;
+use percent_encoding::percent_decode_str;
+
+/// Whether href is a `data:` URI.
+pub fn is_data(href: &str) -> bool {
+    href.get(..5)
+        .map_or(false, |scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+/// Decode a `data:` URI into its media type and data. The data may be base64
+/// or percent encoded.
+pub fn decode(uri: &str) -> Result<(String, Vec<u8>)> {
+    if !is_data(uri) {
+        return Err(Error::Scrape("not a data uri".into()));
+    }
+    let (header, body) = match uri[5..].find(',') {
+        Some(ii) => (&uri[5..5 + ii], &uri[5 + ii + 1..]),
+        None => return Err(Error::Scrape("data uri has no ',' before its data".into())),
+    };
+    let mut params = header.split(';').map(str::trim);
+    let media = match params.next() {
+        Some(media) if !media.is_empty() => media.to_lowercase(),
+        _ => "text/plain".into(),
+    };
+    let is_base64 = params.any(|param| param.eq_ignore_ascii_case("base64"));
+    let data: Vec<u8> = percent_decode_str(body).collect();
+    if !is_base64 {
+        return Ok((media, data));
+    }
+    // Markup often wraps long data uris over several lines.
+    let data: Vec<u8> = data
+        .into_iter()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    match base64::decode(&data) {
+        Ok(data) => Ok((media, data)),
+        Err(err) => Err(Error::Scrape(format!("decoding base64 data uri: {}", err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes() {
+        for (uri, expected) in &[
+            ("data:image/png;base64,aWNvbg==", Some(("image/png", "icon"))),
+            ("DATA:Image/PNG;BASE64,aWNvbg==", Some(("image/png", "icon"))),
+            (
+                "data:image/svg+xml,%3Csvg%20xmlns%3D%22http%3A%2F%2Fwww.w3.org%2F2000%2Fsvg%22%2F%3E",
+                Some(("image/svg+xml", r#"<svg xmlns="http://www.w3.org/2000/svg"/>"#)),
+            ),
+            (
+                "data:image/svg+xml;charset=utf-8,<svg>%E2%98%85</svg>",
+                Some(("image/svg+xml", "<svg>★</svg>")),
+            ),
+            // Wrapped over several lines in the markup.
+            ("data:image/png;base64,\n  aWNv\n  bg==\n", Some(("image/png", "icon"))),
+            ("data:image/png ; base64,aWNvbg==", Some(("image/png", "icon"))),
+            ("data:;base64,aWNvbg==", Some(("text/plain", "icon"))),
+            ("data:,icon", Some(("text/plain", "icon"))),
+            ("data:image/png;base64", None),
+            ("data:image/png", None),
+            ("data:image/png;base64,!!!!", None),
+            ("http://example.com/icon.png", None),
+            ("data", None),
+        ] {
+            let decoded = decode(uri).ok();
+            let expected = expected.map(|(media, data)| (media.to_string(), data.as_bytes().to_vec()));
+            assert_eq!(decoded, expected, "{}", uri);
+        }
+    }
+}
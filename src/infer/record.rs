@@ -0,0 +1,278 @@
+//! Downloaders that record responses to a directory and replay them, so that
+//! an inference can be reproduced offline.
+//!
+//! A recording is a directory holding `recording.json`, which lists every
+//! request made along with the response it got, and a `.body` file per
+//! response. Failed requests are recorded with their error instead, and
+//! headers that carry credentials, such as Set-Cookie, are left out.
+use super::{Downloader, Request, Response, Result};
+use crate::error::Error;
+use log::debug;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Response headers that carry credentials, which a recording may not be
+/// trusted with.
+const SENSITIVE: &[&str] = &[
+    "set-cookie",
+    "set-cookie2",
+    "authentication-info",
+    "proxy-authentication-info",
+];
+
+/// Recorder is a Downloader that saves every response it gets from inner.
+#[derive(Clone)]
+pub struct Recorder<D: Downloader> {
+    /// Directory to save the recording in.
+    pub dir: PathBuf,
+    /// Downloader whose responses are recorded.
+    pub inner: D,
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl<D: Downloader> Recorder<D> {
+    /// Record responses from inner into dir, replacing any recording already
+    /// there.
+    pub fn new(dir: &Path, inner: D) -> Result<Recorder<D>> {
+        fs::create_dir_all(dir)?;
+        Ok(Recorder {
+            dir: dir.into(),
+            inner,
+            recording: Arc::new(Mutex::new(Recording::default())),
+        })
+    }
+
+    /// Save entry, along with body if the request got a response, replacing
+    /// any earlier entry for the same url.
+    fn save(&self, mut entry: Entry, body: Option<&[u8]>) -> Result<()> {
+        let mut recording = self.recording.lock().expect("locking recording");
+        // A url fetched twice keeps its latest response.
+        recording
+            .entries
+            .retain(|recorded| recorded.request != entry.request);
+        if let Some(body) = body {
+            entry.body = format!("{}.body", recording.next);
+            recording.next += 1;
+            fs::write(self.dir.join(&entry.body), body)?;
+        }
+        recording.entries.push(entry);
+        let index =
+            serde_json::to_vec_pretty(&*recording).map_err(|err| Error::Download(Box::new(err)))?;
+        fs::write(self.dir.join("recording.json"), index)?;
+        Ok(())
+    }
+}
+
+impl<D: Downloader> Downloader for Recorder<D> {
+    fn fetch(&self, req: Request) -> Result<Response> {
+        let url = req.url.clone();
+        let mut response = match self.inner.fetch(req) {
+            Ok(response) => response,
+            Err(err) => {
+                // Replay wraps the message as a download error again.
+                let message = match &err {
+                    Error::Download(inner) => inner.to_string(),
+                    err => err.to_string(),
+                };
+                let entry = Entry {
+                    request: url.clone(),
+                    url,
+                    status: 0,
+                    headers: vec![],
+                    body: String::new(),
+                    error: Some(message),
+                };
+                self.save(entry, None)?;
+                return Err(err);
+            }
+        };
+        let mut body = vec![];
+        response.body.read_to_end(&mut body)?;
+        let entry = Entry {
+            request: url,
+            url: response.url.clone(),
+            status: response.status.as_u16(),
+            headers: response
+                .headers
+                .iter()
+                .filter(|(name, _)| !SENSITIVE.contains(&name.as_str()))
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.into())))
+                .collect(),
+            body: String::new(),
+            error: None,
+        };
+        self.save(entry, Some(&body))?;
+        response.body = Box::new(Cursor::new(body));
+        Ok(response)
+    }
+}
+
+/// Replay is a Downloader that serves the responses saved by a Recorder,
+/// without touching the network. Requests that failed when recorded fail
+/// again with the same error, and requests that weren't recorded fail.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    /// Directory the recording was saved in.
+    pub dir: PathBuf,
+    entries: Arc<HashMap<String, Entry>>,
+}
+
+impl Replay {
+    /// Open the recording saved in dir.
+    pub fn open(dir: &Path) -> Result<Replay> {
+        let recording: Recording =
+            serde_json::from_slice(&fs::read(dir.join("recording.json"))?)
+                .map_err(|err| Error::Download(format!("parsing recording: {}", err).into()))?;
+        Ok(Replay {
+            dir: dir.into(),
+            entries: Arc::new(
+                recording
+                    .entries
+                    .into_iter()
+                    .map(|entry| (entry.request.clone(), entry))
+                    .collect(),
+            ),
+        })
+    }
+}
+
+impl Downloader for Replay {
+    fn fetch(&self, req: Request) -> Result<Response> {
+        let entry = match self.entries.get(&req.url) {
+            Some(entry) => entry,
+            None => {
+                return Err(Error::Download(
+                    format!("no recorded response for {}", &req.url).into(),
+                ))
+            }
+        };
+        debug!("replaying {}", &req.url);
+        if let Some(error) = &entry.error {
+            return Err(Error::Download(error.clone().into()));
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in &entry.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        Ok(Response {
+            url: entry.url.clone(),
+            status: StatusCode::from_u16(entry.status).unwrap_or(StatusCode::OK),
+            headers,
+            body: Box::new(Cursor::new(fs::read(self.dir.join(&entry.body))?)),
+        })
+    }
+}
+
+/// Recording is the contents of `recording.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Recording {
+    entries: Vec<Entry>,
+    /// Number used to name the next body file.
+    #[serde(default)]
+    next: usize,
+}
+
+/// Entry is a recorded request and its response.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// Url that was requested.
+    request: String,
+    /// Final url of the response, after any redirects.
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// Name of the file holding the body, relative to the recording.
+    body: String,
+    /// Error the request failed with, in which case there is no response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infer::tempdir::TempDir;
+    use reqwest::header::{CONTENT_TYPE, SET_COOKIE};
+
+    /// Origin serves a page that sets a cookie, and fails any other request.
+    struct Origin;
+
+    impl Downloader for Origin {
+        fn fetch(&self, req: Request) -> Result<Response> {
+            if !req.url.ends_with('/') {
+                return Err(Error::Download(
+                    format!("connecting to {}: refused", &req.url).into(),
+                ));
+            }
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+            headers.insert(SET_COOKIE, HeaderValue::from_static("session=secret"));
+            Ok(Response {
+                url: req.url,
+                status: StatusCode::OK,
+                headers,
+                body: Box::new(Cursor::new(b"<title>Site</title>".to_vec())),
+            })
+        }
+    }
+
+    /// Record a page and a failed request into dir.
+    fn record(dir: &Path) {
+        let recorder = Recorder::new(dir, Origin).unwrap();
+        recorder.get("https://example.com/").unwrap();
+        assert!(recorder.get("https://example.com/favicon.ico").is_err());
+    }
+
+    #[test]
+    fn replays_responses() {
+        let dir = TempDir::new("responses");
+        record(&dir);
+        let replay = Replay::open(&dir).unwrap();
+        let mut response = replay.fetch(Request::new("https://example.com/")).unwrap();
+        assert_eq!(response.headers[CONTENT_TYPE], "text/html");
+        let mut body = String::new();
+        response.body.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "<title>Site</title>");
+    }
+
+    #[test]
+    fn omits_credentials() {
+        let dir = TempDir::new("credentials");
+        record(&dir);
+        let recording = fs::read_to_string(dir.join("recording.json")).unwrap();
+        assert!(!recording.to_lowercase().contains("set-cookie"));
+        assert!(!recording.contains("secret"));
+    }
+
+    #[test]
+    fn replays_errors() {
+        let dir = TempDir::new("errors");
+        record(&dir);
+        let replay = Replay::open(&dir).unwrap();
+        let err = replay
+            .fetch(Request::new("https://example.com/favicon.ico"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "downloading: connecting to https://example.com/favicon.ico: refused"
+        );
+        let err = replay
+            .fetch(Request::new("https://example.com/other"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no recorded response"));
+    }
+}
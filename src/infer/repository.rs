@@ -0,0 +1,247 @@
+use super::{Downloader, Icon, Result};
+use crate::error::Error;
+use log::debug;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use url::Url;
+
+/// IconRepository provides curated icons, which are preferred over icons
+/// scraped from the site itself.
+pub trait IconRepository {
+    /// Find the icon for url, if the repository has one.
+    fn lookup(&self, url: &Url) -> Result<Option<Icon>>;
+}
+
+/// Location of an icon repository.
+#[derive(Debug, Clone)]
+pub enum Location {
+    /// Local directory.
+    Dir(PathBuf),
+    /// Base url of a remote repository.
+    Url(Url),
+}
+
+impl Location {
+    /// Parse a location, treating anything that isn't an http(s) url as a
+    /// local directory.
+    pub fn parse(location: &str) -> Location {
+        match Url::parse(location) {
+            Ok(mut url) if url.scheme() == "http" || url.scheme() == "https" => {
+                if !url.path().ends_with('/') {
+                    let path = format!("{}/", url.path());
+                    url.set_path(&path);
+                }
+                Location::Url(url)
+            }
+            _ => Location::Dir(PathBuf::from(location)),
+        }
+    }
+}
+
+/// Repository is an IconRepository described by an "index.json" file, which
+/// maps host patterns to icon files relative to the index:
+///
+/// ```json
+/// {
+///     "github.com": "github.png",
+///     "*.google.com": "google.svg",
+///     "mail.google.com": "gmail.png"
+/// }
+/// ```
+///
+/// Hosts are matched without any leading "www.", and a `*` in a pattern
+/// matches any run of characters. When several patterns match a host, an
+/// exact match wins, followed by the longest pattern.
+///
+/// Icon files must stay within the repository: absolute paths, urls and ".."
+/// components are rejected.
+pub struct Repository<D: Downloader> {
+    pub location: Location,
+    /// client downloads the index and icons of remote repositories.
+    pub client: D,
+}
+
+impl<D: Downloader> Repository<D> {
+    fn index(&self) -> Result<BTreeMap<String, String>> {
+        let buf = match &self.location {
+            Location::Dir(dir) => fs::read(dir.join("index.json"))?,
+            Location::Url(base) => {
+                let mut buf = vec![];
+                self.client
+                    .get(base.join("index.json")?.as_str())?
+                    .read_to_end(&mut buf)?;
+                buf
+            }
+        };
+        serde_json::from_slice(&buf)
+            .map_err(|err| Error::Repository(format!("parsing index: {}", err)))
+    }
+}
+
+impl<D: Downloader> IconRepository for Repository<D> {
+    fn lookup(&self, url: &Url) -> Result<Option<Icon>> {
+        let host = match url.host_str() {
+            Some(host) => host.trim_start_matches("www."),
+            None => return Ok(None),
+        };
+        let index = self.index()?;
+        let file = match index.get(host) {
+            Some(file) => file,
+            None => match index
+                .iter()
+                .filter(|(pattern, _)| matches(pattern, host))
+                .max_by_key(|(pattern, _)| pattern.len())
+            {
+                Some((_, file)) => file,
+                None => return Ok(None),
+            },
+        };
+        let file = relative(file)?;
+        debug!("icon repository has {} for {}", file, host);
+        let icon = match &self.location {
+            Location::Dir(dir) => Icon::open(&dir.join(file))?,
+            Location::Url(base) => Icon::download(&self.client, base.join(file)?.as_str())?,
+        };
+        Ok(Some(icon))
+    }
+}
+
+/// Check that file, from the index, names a file within the repository.
+fn relative(file: &str) -> Result<&str> {
+    if file.is_empty()
+        || file.starts_with('/')
+        || file.starts_with('\\')
+        || file.contains(':')
+        || file.split(&['/', '\\'][..]).any(|part| part == "..")
+    {
+        return Err(Error::Repository(format!(
+            "icon {:?} is outside the repository",
+            file
+        )));
+    }
+    Ok(file)
+}
+
+/// Match host against a pattern where `*` matches any run of characters.
+fn matches(pattern: &str, host: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == host;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !host.starts_with(first) || host.len() < first.len() + last.len() {
+        return false;
+    }
+    let mut rest = &host[first.len()..];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(ii) => rest = &rest[ii + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infer::tempdir::TempDir;
+    use std::path::Path;
+
+    #[test]
+    fn wildcards() {
+        for (pattern, host, expected) in &[
+            ("github.com", "github.com", true),
+            ("github.com", "gist.github.com", false),
+            ("*.google.com", "mail.google.com", true),
+            ("*.google.com", "google.com", false),
+            ("*.google.com", "mail.google.com.evil.com", false),
+            ("mail.*.com", "mail.google.com", true),
+            ("mail.*.com", "mail.com", false),
+            ("*", "example.com", true),
+            ("a*b*c", "abc", true),
+            ("a*b*c", "acb", false),
+            ("*oo*oo*", "foo", false),
+        ] {
+            assert_eq!(matches(pattern, host), *expected, "{} ~ {}", pattern, host);
+        }
+    }
+
+    #[test]
+    fn relative_files() {
+        assert!(relative("github.png").is_ok());
+        assert!(relative("google/mail.png").is_ok());
+        assert!(relative("..png").is_ok());
+        for file in &[
+            "",
+            "/etc/passwd",
+            "\\\\server\\share.png",
+            "C:\\icon.png",
+            "https://example.com/icon.png",
+            "//example.com/icon.png",
+            "../icon.png",
+            "icons/../../icon.png",
+            "icons\\..\\..\\icon.png",
+        ] {
+            assert!(relative(file).is_err(), "{:?}", file);
+        }
+    }
+
+    fn repository(dir: &Path, index: &str, files: &[(&str, u32)]) -> Repository<reqwest::Client> {
+        for (file, size) in files {
+            image::RgbaImage::new(*size, *size)
+                .save(dir.join(file))
+                .unwrap();
+        }
+        fs::write(dir.join("index.json"), index).unwrap();
+        Repository {
+            location: Location::Dir(dir.into()),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn lookup<D: Downloader>(repository: &Repository<D>, url: &str) -> Option<u32> {
+        repository
+            .lookup(&Url::parse(url).unwrap())
+            .unwrap()
+            .map(|icon| icon.img.width())
+    }
+
+    #[test]
+    fn lookup_precedence() {
+        let dir = TempDir::new("precedence");
+        let repository = repository(
+            &dir,
+            r#"{
+                "mail.google.com": "exact.png",
+                "*.google.com": "wildcard.png",
+                "*.com": "short.png",
+                "github.com": "exact.png"
+            }"#,
+            &[("exact.png", 16), ("wildcard.png", 32), ("short.png", 48)],
+        );
+        assert_eq!(
+            lookup(&repository, "https://mail.google.com/mail"),
+            Some(16)
+        );
+        assert_eq!(lookup(&repository, "https://docs.google.com"), Some(32));
+        assert_eq!(lookup(&repository, "https://example.com"), Some(48));
+        assert_eq!(lookup(&repository, "https://www.github.com"), Some(16));
+        assert_eq!(lookup(&repository, "https://www.mail.google.com"), Some(16));
+        assert_eq!(lookup(&repository, "https://example.org"), None);
+    }
+
+    #[test]
+    fn lookup_outside() {
+        let dir = TempDir::new("outside");
+        let repository = repository(&dir, r#"{"example.com": "../outside.png"}"#, &[]);
+        let url = Url::parse("https://example.com").unwrap();
+        match repository.lookup(&url) {
+            Err(Error::Repository(_)) => {}
+            result => panic!("expected a repository error, got {:?}", result.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Scratch directories for tests.
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directories created so far by this process.
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// TempDir is an empty directory unique to one test of one process, removed
+/// when dropped, so that concurrent test runs don't trample each other.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create a directory for the test called name.
+    pub fn new(name: &str) -> TempDir {
+        let dir = env::temp_dir().join(format!(
+            "nativefier-test-{}-{}-{}",
+            process::id(),
+            CREATED.fetch_add(1, Ordering::SeqCst),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,81 @@
+//! Windows tile images, declared in markup or in a browserconfig.xml file.
+//! See https://docs.microsoft.com/en-us/previous-versions/windows/internet-explorer/ie-developer/platform-apis/dn320426(v=vs.85).
+use super::page::{resolve, Page};
+use super::Link;
+use scraper::{Html, Selector};
+use url::Url;
+
+/// Square logos a browserconfig file can declare, with their sizes.
+const LOGOS: &[(&str, &str)] = &[
+    ("square70x70logo", "70x70"),
+    ("square150x150logo", "150x150"),
+    ("square310x310logo", "310x310"),
+];
+
+/// Whether rel is that of a tile image. Tiles are usually a white logo meant
+/// to be drawn over the tile's color.
+pub fn is_tile(rel: &str) -> bool {
+    rel == "msapplication-TileImage" || LOGOS.iter().any(|&(name, _)| rel == name)
+}
+
+/// Find the tile image declared by `<meta name="msapplication-TileImage">`.
+pub fn image(page: &Page) -> Option<Link> {
+    let meta_el = Selector::parse(r#"meta[name="msapplication-TileImage"]"#).unwrap();
+    let href = page
+        .doc
+        .select(&meta_el)
+        .filter_map(|el| el.value().attr("content"))
+        .filter_map(|href| page.resolve(href))
+        .next()?;
+    Some(Link {
+        href,
+        rel: "msapplication-TileImage".into(),
+        sizes: None,
+        color: None,
+    })
+}
+
+/// Find the url of the site's browserconfig file: the one named by
+/// `<meta name="msapplication-config">`, or "/browserconfig.xml" for sites
+/// served over http. Sites can opt out with a value of "none".
+pub fn config(page: &Page) -> Option<String> {
+    let meta_el = Selector::parse(r#"meta[name="msapplication-config"]"#).unwrap();
+    match page
+        .doc
+        .select(&meta_el)
+        .filter_map(|el| el.value().attr("content"))
+        .next()
+    {
+        Some(href) if href.trim().eq_ignore_ascii_case("none") => None,
+        Some(href) => page.resolve(href),
+        None if page.url.scheme().starts_with("http") => page
+            .url
+            .join("/browserconfig.xml")
+            .ok()
+            .map(Url::into_string),
+        None => None,
+    }
+}
+
+/// Extract the square logos from a browserconfig file downloaded from url.
+pub fn logos(url: &Url, xml: &str) -> Vec<Link> {
+    // The html parser is lenient enough for the simple xml used here, though
+    // it lowercases element names.
+    let doc = Html::parse_fragment(xml);
+    LOGOS
+        .iter()
+        .flat_map(|&(name, sizes)| {
+            let logo_el = Selector::parse(&format!("{}[src]", name)).unwrap();
+            doc.select(&logo_el)
+                .filter_map(|el| el.value().attr("src"))
+                .filter_map(|src| resolve(url, src))
+                .map(|href| Link {
+                    href,
+                    rel: name.into(),
+                    sizes: Some(sizes.into()),
+                    color: None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
@@ -0,0 +1,488 @@
+//! Transforms that make a chosen icon look at home in a dock or taskbar:
+//! trimming transparent borders, padding, backgrounds and masks, and scaling
+//! that enlarges small icons without blurring them.
+use super::color;
+use super::Icon;
+use image::imageops::{overlay, resize, Lanczos3, Nearest};
+use image::{Rgba, RgbaImage};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Largest padding, as a fraction of the icon's size, that leaves something
+/// of the icon to see.
+const MAX_PADDING: f32 = 0.45;
+
+/// Largest icon, in pixels, that's enlarged like pixel art when upscaling
+/// automatically. Anything bigger is smoothed.
+const TINY: u32 = 32;
+
+/// Most colors an icon can have and still be treated as pixel art.
+const PIXEL_ART_COLORS: usize = 16;
+
+/// Transform is applied to the chosen icon before it's bundled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    /// Remove transparent borders, so that the icon fills its space.
+    pub trim: bool,
+    /// Space left around the icon on each side, as a fraction of its size.
+    pub padding: f32,
+    pub background: Background,
+    pub mask: Mask,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            trim: true,
+            padding: 0.0,
+            background: Background::None,
+            mask: Mask::None,
+        }
+    }
+}
+
+/// Upscale is how an icon smaller than a bundle needs is enlarged by `scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Upscale {
+    /// Pixel art scaling for tiny icons with few colors, nearest neighbor for
+    /// other tiny icons and smooth scaling for the rest.
+    Auto,
+    /// Lanczos resampling, which blurs small sources.
+    Smooth,
+    /// Nearest neighbor scaling by a whole factor, keeping pixels crisp.
+    Nearest,
+    /// Scale2x, which keeps pixels crisp while smoothing diagonal edges.
+    PixelArt,
+    /// Enlarge the icon to at most half the size, centered on a generated
+    /// rounded background, rather than stretching it to fill.
+    Center,
+}
+
+impl FromStr for Upscale {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Upscale, String> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(Upscale::Auto),
+            "smooth" => Ok(Upscale::Smooth),
+            "nearest" => Ok(Upscale::Nearest),
+            "pixel-art" => Ok(Upscale::PixelArt),
+            "center" => Ok(Upscale::Center),
+            _ => Err(format!(
+                "{:?} is not an upscaling method: use auto, smooth, nearest, pixel-art or center",
+                s
+            )),
+        }
+    }
+}
+
+/// Background is the color put behind an icon's transparent parts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    /// Leave transparent parts transparent.
+    None,
+    Color(Rgba<u8>),
+    /// The site's theme color, or white or a dark grey, whichever stands out
+    /// from the icon.
+    Auto,
+}
+
+impl FromStr for Background {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Background, String> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Background::None),
+            "auto" => Ok(Background::Auto),
+            _ => color::parse(s)
+                .map(Background::Color)
+                .ok_or_else(|| format!("{:?} is not a color, \"auto\" or \"none\"", s)),
+        }
+    }
+}
+
+/// Mask is the shape an icon is cut to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mask {
+    None,
+    Circle,
+    /// A square with rounded corners, like generated icons.
+    Rounded,
+    /// A superellipse, the shape of iOS and macOS app icons.
+    Squircle,
+}
+
+impl FromStr for Mask {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Mask, String> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Mask::None),
+            "circle" => Ok(Mask::Circle),
+            "rounded" => Ok(Mask::Rounded),
+            "squircle" => Ok(Mask::Squircle),
+            _ => Err(format!(
+                "{:?} is not a mask: use none, circle, rounded or squircle",
+                s
+            )),
+        }
+    }
+}
+
+impl Transform {
+    /// Apply the transform to icon. An automatic background uses theme, the
+    /// site's theme color, if it stands out from the icon. Enlarging small
+    /// icons is left to the bundlers, which `scale` them to the sizes they
+    /// need.
+    ///
+    /// An icon that's changed loses its original file, which no longer
+    /// matches the image. Icons the transform has nothing to do to, such as a
+    /// square icon without transparent borders, are left alone and keep it,
+    /// so that bundlers can still embed the file as is.
+    pub fn apply(&self, icon: &mut Icon, theme: Option<Rgba<u8>>) {
+        let mut changed = false;
+        let mut img = icon.img.clone();
+        if self.trim {
+            if let Some(trimmed) = trim(&img) {
+                img = trimmed;
+                changed = true;
+            }
+        }
+        let padding = self.padding.clamp(0.0, MAX_PADDING);
+        let side = img.width().max(img.height());
+        let side = (side as f32 / (1.0 - 2.0 * padding)).round() as u32;
+        if side != img.width() || side != img.height() {
+            let mut canvas = RgbaImage::new(side, side);
+            overlay(
+                &mut canvas,
+                &img,
+                (side - img.width()) / 2,
+                (side - img.height()) / 2,
+            );
+            img = canvas;
+            changed = true;
+        }
+        let background = match self.background {
+            Background::None => None,
+            Background::Color(color) => Some(color),
+            Background::Auto => Some(auto_background(&img, theme)),
+        };
+        // A background only shows through transparent pixels.
+        if let Some(background) = background.filter(|_| img.pixels().any(|p| p[3] < 255)) {
+            let mut canvas = RgbaImage::from_pixel(img.width(), img.height(), background);
+            overlay(&mut canvas, &img, 0, 0);
+            img = canvas;
+            changed = true;
+        }
+        if self.mask != Mask::None {
+            mask(&mut img, self.mask);
+            changed = true;
+        }
+        if changed {
+            icon.img = img;
+            icon.original = None;
+        }
+    }
+}
+
+/// Scale img to fit a size by size square, enlarging smaller images with the
+/// given method. Images enlarged by a whole factor are centered on a
+/// transparent square when the factor doesn't fill it exactly.
+///
+/// Bundlers scale icons to the sizes they embed with this, rather than
+/// leaving the image encoder to blur them.
+pub fn scale(img: &RgbaImage, size: u32, upscale: Upscale) -> RgbaImage {
+    let side = img.width().max(img.height());
+    if side == 0 || side == size {
+        return img.clone();
+    }
+    let smooth = || {
+        resize(
+            img,
+            (img.width() * size / side).max(1),
+            (img.height() * size / side).max(1),
+            Lanczos3,
+        )
+    };
+    let upscale = match upscale {
+        Upscale::Auto if side > TINY => Upscale::Smooth,
+        Upscale::Auto if is_pixel_art(img) => Upscale::PixelArt,
+        Upscale::Auto => Upscale::Nearest,
+        upscale => upscale,
+    };
+    if side > size {
+        return smooth();
+    }
+    let scaled = match upscale {
+        Upscale::Smooth | Upscale::Auto => return smooth(),
+        Upscale::Nearest => nearest(img, size / side),
+        Upscale::PixelArt => {
+            let mut scaled = img.clone();
+            while scaled.width().max(scaled.height()) * 2 <= size {
+                scaled = scale2x(&scaled);
+            }
+            let factor = size / scaled.width().max(scaled.height());
+            nearest(&scaled, factor)
+        }
+        Upscale::Center => nearest(img, (size / 2 / side).max(1)),
+    };
+    // Centered icons sit on a generated background, like generated icons.
+    let mut canvas = match upscale {
+        Upscale::Center => RgbaImage::from_pixel(size, size, auto_background(img, None)),
+        _ => RgbaImage::new(size, size),
+    };
+    overlay(
+        &mut canvas,
+        &scaled,
+        (size - scaled.width()) / 2,
+        (size - scaled.height()) / 2,
+    );
+    if upscale == Upscale::Center {
+        mask(&mut canvas, Mask::Rounded);
+    }
+    canvas
+}
+
+/// Whether img looks like pixel art: hard edged, with only a few colors.
+fn is_pixel_art(img: &RgbaImage) -> bool {
+    let mut colors = HashSet::new();
+    for pixel in img.pixels() {
+        // Anti-aliased edges give partly transparent pixels.
+        if pixel[3] != 0 && pixel[3] != 255 {
+            return false;
+        }
+        if pixel[3] == 255 && colors.insert(*pixel) && colors.len() > PIXEL_ART_COLORS {
+            return false;
+        }
+    }
+    true
+}
+
+/// Enlarge img by a whole factor, repeating each pixel.
+fn nearest(img: &RgbaImage, factor: u32) -> RgbaImage {
+    resize(img, img.width() * factor, img.height() * factor, Nearest)
+}
+
+/// Double the size of img with the Scale2x algorithm, which fills in the
+/// corners of diagonal edges instead of leaving steps.
+/// See https://www.scale2x.it/algorithm.
+fn scale2x(img: &RgbaImage) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let at = |x: i64, y: i64| {
+        *img.get_pixel(
+            x.clamp(0, i64::from(width) - 1) as u32,
+            y.clamp(0, i64::from(height) - 1) as u32,
+        )
+    };
+    let mut out = RgbaImage::new(width * 2, height * 2);
+    for (x, y, &p) in img.enumerate_pixels() {
+        let (x, y) = (i64::from(x), i64::from(y));
+        let (above, right, left, below) = (at(x, y - 1), at(x + 1, y), at(x - 1, y), at(x, y + 1));
+        let (mut e0, mut e1, mut e2, mut e3) = (p, p, p, p);
+        if above != below && left != right {
+            if left == above {
+                e0 = left;
+            }
+            if above == right {
+                e1 = right;
+            }
+            if left == below {
+                e2 = left;
+            }
+            if below == right {
+                e3 = right;
+            }
+        }
+        let (x, y) = (x as u32 * 2, y as u32 * 2);
+        out.put_pixel(x, y, e0);
+        out.put_pixel(x + 1, y, e1);
+        out.put_pixel(x, y + 1, e2);
+        out.put_pixel(x + 1, y + 1, e3);
+    }
+    out
+}
+
+/// Crop the fully transparent rows and columns from the edges of img, if it
+/// has any and isn't transparent throughout.
+fn trim(img: &RgbaImage) -> Option<RgbaImage> {
+    let (mut left, mut top, mut right, mut bottom) = (img.width(), img.height(), 0, 0);
+    for (x, y, pixel) in img.enumerate_pixels() {
+        if pixel[3] > 0 {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+    }
+    // Leave fully transparent images alone rather than cropping them away.
+    if left >= right || top >= bottom || (right - left, bottom - top) == img.dimensions() {
+        return None;
+    }
+    Some(image::imageops::crop(&mut img.clone(), left, top, right - left, bottom - top).to_image())
+}
+
+/// Pick a background for img: theme if it stands out from the icon's
+/// dominant color, otherwise white or a dark grey.
+fn auto_background(img: &RgbaImage, theme: Option<Rgba<u8>>) -> Rgba<u8> {
+    let dominant = color::dominant(img).map(color::luminance);
+    let stands_out =
+        |color: Rgba<u8>| dominant.map_or(true, |d| (color::luminance(color) - d).abs() >= 0.3);
+    match theme.map(|Rgba([r, g, b, _])| Rgba([r, g, b, 255])) {
+        Some(theme) if stands_out(theme) => theme,
+        _ if dominant.map_or(false, |d| d > 0.6) => Rgba([51, 51, 51, 255]),
+        _ => Rgba([255, 255, 255, 255]),
+    }
+}
+
+/// Cut img to shape, anti-aliasing its edge by sampling each pixel on a grid.
+fn mask(img: &mut RgbaImage, shape: Mask) {
+    const SAMPLES: u32 = 4;
+    let (width, height) = img.dimensions();
+    let inside = |x: f32, y: f32| match shape {
+        Mask::None => true,
+        Mask::Circle => x * x + y * y <= 1.0,
+        Mask::Rounded => {
+            // Corners have a radius of a fifth of the side, as in generated
+            // icons.
+            let radius = 0.4;
+            let (cx, cy) = (
+                (x.abs() - (1.0 - radius)).max(0.0),
+                (y.abs() - (1.0 - radius)).max(0.0),
+            );
+            cx * cx + cy * cy <= radius * radius
+        }
+        Mask::Squircle => x.abs().powi(5) + y.abs().powi(5) <= 1.0,
+    };
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let mut hits = 0;
+        for sy in 0..SAMPLES {
+            for sx in 0..SAMPLES {
+                // Sample coordinates, scaled so the image spans -1 to 1.
+                let u = (x as f32 + (sx as f32 + 0.5) / SAMPLES as f32) / width as f32 * 2.0 - 1.0;
+                let v = (y as f32 + (sy as f32 + 0.5) / SAMPLES as f32) / height as f32 * 2.0 - 1.0;
+                if inside(u, v) {
+                    hits += 1;
+                }
+            }
+        }
+        pixel[3] = (u32::from(pixel[3]) * hits / (SAMPLES * SAMPLES)) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infer::Original;
+    use std::sync::Arc;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    fn icon(img: RgbaImage) -> Icon {
+        Icon {
+            source: "favicon.ico".into(),
+            name: "favicon".into(),
+            ext: "ico".into(),
+            img,
+            original: Some(Original {
+                mime: "image/x-icon".into(),
+                data: Arc::new(vec![]),
+            }),
+        }
+    }
+
+    fn apply(transform: Transform, img: RgbaImage) -> Icon {
+        let mut icon = icon(img);
+        transform.apply(&mut icon, None);
+        icon
+    }
+
+    #[test]
+    fn no_op() {
+        let transform = Transform::default();
+        let opaque = RgbaImage::from_pixel(16, 16, RED);
+        for transform in &[
+            transform,
+            Transform {
+                background: Background::Auto,
+                ..transform
+            },
+            Transform {
+                background: Background::Color(Rgba([0, 0, 0, 255])),
+                ..transform
+            },
+        ] {
+            let icon = apply(*transform, opaque.clone());
+            assert!(icon.original.is_some(), "{:?}", transform);
+            assert_eq!(*icon.img, *opaque);
+        }
+        // Nothing to trim from a transparent icon, or one that's too wide.
+        assert!(apply(transform, RgbaImage::new(8, 8)).original.is_some());
+        let wide = RgbaImage::from_pixel(16, 8, RED);
+        let icon = apply(transform, wide);
+        assert!(icon.original.is_none());
+        assert_eq!(icon.img.dimensions(), (16, 16));
+    }
+
+    #[test]
+    fn trims() {
+        let mut img = RgbaImage::new(40, 40);
+        for y in 8..18 {
+            for x in 5..25 {
+                img.put_pixel(x, y, RED);
+            }
+        }
+        let transform = Transform::default();
+        let icon = apply(transform, img.clone());
+        assert!(icon.original.is_none());
+        assert_eq!(icon.img.dimensions(), (20, 20));
+        assert_eq!(icon.img.get_pixel(0, 0)[3], 0);
+        assert_eq!(*icon.img.get_pixel(0, 5), RED);
+        let untrimmed = apply(
+            Transform {
+                trim: false,
+                ..transform
+            },
+            img,
+        );
+        assert!(untrimmed.original.is_some());
+    }
+
+    #[test]
+    fn scales() {
+        let blue = Rgba([0, 0, 255, 255]);
+        let checker = RgbaImage::from_fn(16, 16, |x, y| if (x + y) % 2 == 0 { RED } else { blue });
+        // Tiny icons with few colors stay crisp.
+        let scaled = scale(&checker, 512, Upscale::Auto);
+        assert_eq!(scaled.dimensions(), (512, 512));
+        assert!(scaled.pixels().all(|p| *p == RED || *p == blue));
+        // Larger ones are smoothed, and large ones shrunk.
+        let halves = RgbaImage::from_fn(64, 64, |x, _| if x < 32 { RED } else { blue });
+        let scaled = scale(&halves, 256, Upscale::Auto);
+        assert!(scaled.pixels().any(|p| *p != RED && *p != blue));
+        assert_eq!(scale(&halves, 16, Upscale::Nearest).dimensions(), (16, 16));
+        // Centered icons take up half the size, on a rounded background.
+        let scaled = scale(&RgbaImage::from_pixel(16, 16, RED), 512, Upscale::Center);
+        assert_eq!(scaled.dimensions(), (512, 512));
+        assert_eq!(scaled.get_pixel(0, 0)[3], 0);
+        assert_eq!(*scaled.get_pixel(100, 256), Rgba([255, 255, 255, 255]));
+        assert_eq!(*scaled.get_pixel(256, 256), RED);
+        // Transforms leave sizes to the bundlers.
+        let icon = apply(Transform::default(), RgbaImage::from_pixel(32, 32, RED));
+        assert_eq!(icon.img.dimensions(), (32, 32));
+        assert!(icon.original.is_some());
+    }
+
+    #[test]
+    fn pads_masks_and_fills() {
+        let icon = apply(
+            Transform {
+                padding: 0.25,
+                background: Background::Color(Rgba([0, 255, 0, 255])),
+                mask: Mask::Circle,
+                ..Transform::default()
+            },
+            RgbaImage::from_pixel(20, 20, RED),
+        );
+        assert_eq!(icon.img.dimensions(), (40, 40));
+        assert_eq!(icon.img.get_pixel(0, 0)[3], 0);
+        assert_eq!(*icon.img.get_pixel(20, 2), Rgba([0, 255, 0, 255]));
+        assert_eq!(*icon.img.get_pixel(20, 20), RED);
+    }
+}
@@ -0,0 +1,230 @@
+//! Reports on the icon candidates considered for a site.
+use crate::infer::{Candidate, Icon};
+use icns;
+use image::imageops::{resize, Lanczos3};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::BufWriter;
+use std::{fs, path::Path};
+
+/// Longest source shown in a table, since data uris can run to kilobytes.
+const MAX_SOURCE: usize = 60;
+
+/// Format candidates as a table, numbered from 1.
+pub fn table(candidates: &[Candidate]) -> String {
+    let mut rows = vec![[
+        "#".to_string(),
+        "SOURCE".into(),
+        "REL".into(),
+        "DECLARED".into(),
+        "ACTUAL".into(),
+        "FORMAT".into(),
+        "SCORE".into(),
+        "ERROR".into(),
+    ]];
+    for (ii, candidate) in candidates.iter().enumerate() {
+        let icon = candidate.icon.as_ref();
+        rows.push([
+            (ii + 1).to_string(),
+            truncate(&candidate.link.href, MAX_SOURCE),
+            candidate.link.rel.clone(),
+            candidate.link.sizes.clone().unwrap_or_default(),
+            icon.map(|icon| format!("{}x{}", icon.img.width(), icon.img.height()))
+                .unwrap_or_default(),
+            icon.map(|icon| icon.ext.clone()).unwrap_or_default(),
+            icon.map(|icon| icon.score().to_string())
+                .unwrap_or_default(),
+            candidate.error.clone().unwrap_or_default(),
+        ]);
+    }
+    let mut widths = [0; 8];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Format candidates as a json array, in the same order as the table.
+pub fn json(candidates: &[Candidate]) -> Value {
+    Value::Array(
+        candidates
+            .iter()
+            .map(|candidate| {
+                let icon = candidate.icon.as_ref();
+                json!({
+                    "source": candidate.link.href,
+                    "rel": candidate.link.rel,
+                    "declared": candidate.link.sizes,
+                    "width": icon.map(|icon| icon.img.width()),
+                    "height": icon.map(|icon| icon.img.height()),
+                    "format": icon.map(|icon| &icon.ext),
+                    "score": icon.map(|icon| icon.score()),
+                    "error": candidate.error,
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Save icon to path, in the format given by its extension: png, ico or icns.
+pub fn export(icon: &Icon, path: &Path) -> Result<(), Box<dyn Error>> {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    // Keep the original file when it's already in the requested format.
+    let verbatim = match ext.as_str() {
+        "png" => "image/png",
+        "ico" => "image/x-icon",
+        "icns" => "image/icns",
+        _ => "",
+    };
+    if let Some(original) = icon.original.as_ref().filter(|o| o.mime == verbatim) {
+        fs::write(path, &*original.data)?;
+        return Ok(());
+    }
+    match ext.as_str() {
+        "png" => icon.img.save(path)?,
+        "ico" => {
+            // Icons larger than 256 pixels can't be represented in ico files.
+            if icon.img.width() > 256 || icon.img.height() > 256 {
+                resize(&icon.img, 256, 256, Lanczos3).save(path)?
+            } else {
+                icon.img.save(path)?
+            }
+        }
+        "icns" => icns::Encoder::new(BufWriter::new(fs::File::create(path)?)).encode(&icon.img)?,
+        _ => return Err(format!("cannot export to {:?}: use png, ico or icns", ext).into()),
+    }
+    Ok(())
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.into();
+    }
+    let mut truncated: String = s.chars().take(max - 3).collect();
+    truncated.push_str("...");
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infer::tempdir::TempDir;
+    use crate::infer::{Link, Original};
+    use image::RgbaImage;
+    use std::sync::Arc;
+
+    fn candidates() -> Vec<Candidate> {
+        let link = |href: &str, sizes: Option<&str>| Link {
+            href: href.into(),
+            rel: "icon".into(),
+            sizes: sizes.map(Into::into),
+            color: None,
+        };
+        vec![
+            Candidate {
+                link: link("https://example.com/icon.png", Some("32x32")),
+                icon: Some(icon(Some("image/png"))),
+                error: None,
+            },
+            Candidate {
+                link: link(&format!("data:image/png;base64,{}", "A".repeat(80)), None),
+                icon: None,
+                error: Some("decoding image: bad".into()),
+            },
+        ]
+    }
+
+    fn icon(mime: Option<&str>) -> Icon {
+        Icon {
+            source: "https://example.com/icon.png".into(),
+            name: "icon".into(),
+            ext: "png".into(),
+            img: RgbaImage::new(32, 16),
+            original: mime.map(|mime| Original {
+                mime: mime.into(),
+                data: Arc::new(b"original bytes".to_vec()),
+            }),
+        }
+    }
+
+    #[test]
+    fn tables() {
+        let source = format!("data:image/png;base64,{}...", "A".repeat(35));
+        let expected = format!(
+            "#  SOURCE{pad}  REL   DECLARED  ACTUAL  FORMAT  SCORE  ERROR\n\
+             1  https://example.com/icon.png{short}  icon  32x32     32x16   png     512\n\
+             2  {source}  icon                                   decoding image: bad\n",
+            pad = " ".repeat(MAX_SOURCE - "SOURCE".len()),
+            short = " ".repeat(MAX_SOURCE - "https://example.com/icon.png".len()),
+            source = source,
+        );
+        assert_eq!(source.chars().count(), MAX_SOURCE);
+        assert_eq!(table(&candidates()), expected);
+    }
+
+    #[test]
+    fn jsons() {
+        assert_eq!(
+            json(&candidates()),
+            json!([
+                {
+                    "source": "https://example.com/icon.png",
+                    "rel": "icon",
+                    "declared": "32x32",
+                    "width": 32,
+                    "height": 16,
+                    "format": "png",
+                    "score": 512,
+                    "error": null,
+                },
+                {
+                    "source": format!("data:image/png;base64,{}", "A".repeat(80)),
+                    "rel": "icon",
+                    "declared": null,
+                    "width": null,
+                    "height": null,
+                    "format": null,
+                    "score": null,
+                    "error": "decoding image: bad",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn exports() {
+        let dir = TempDir::new("report-exports");
+        // The original is written as is when it's in the requested format,
+        // even though it isn't a valid png.
+        let path = dir.join("verbatim.png");
+        export(&icon(Some("image/png")), &path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"original bytes");
+        // Otherwise the image is encoded afresh.
+        for (mime, name) in &[(None, "encoded.png"), (Some("image/png"), "encoded.ico")] {
+            let path = dir.join(name);
+            export(&icon(*mime), &path).unwrap();
+            let img = image::open(&path).unwrap().to_rgba();
+            assert_eq!(img.dimensions(), (32, 16), "{}", name);
+        }
+        let err = export(&icon(None), &dir.join("icon.gif")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"cannot export to "gif": use png, ico or icns"#
+        );
+    }
+}
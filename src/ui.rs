@@ -4,7 +4,9 @@ mod infer;
 
 use bundle::Bundler;
 use dirs;
+use infer::color::Colors;
 use infer::infer_icon;
+use infer::transform::Upscale;
 use log::{error, trace};
 use pretty_env_logger;
 use serde::{Deserialize, Serialize};
@@ -46,12 +48,15 @@ fn parse_url(url: &str) -> Result<Url, Box<dyn Error>> {
 
 fn build(name: String, url: &Url, directory: String) -> Result<(), Box<dyn ::std::error::Error>> {
     let icon = infer_icon(&url).map_err(|err| format!("inferring icon: {}", err))?;
+    let colors = Colors::default().or_dominant(&icon.img);
     if cfg!(windows) {
         bundle::Windows {
             dir: &directory,
             name: &name,
             url: &url,
             icon: icon,
+            colors,
+            upscale: Upscale::Auto,
         }
         .bundle()
         .map_err(|err| format!("bundling Windows app: {}", err).into())
@@ -61,6 +66,8 @@ fn build(name: String, url: &Url, directory: String) -> Result<(), Box<dyn ::std
             name: &name,
             url: &url,
             icon: icon,
+            colors,
+            upscale: Upscale::Auto,
         }
         .bundle()
         .map_err(|err| format!("bundling MacOS app: {}", err).into())